@@ -10,12 +10,12 @@ async fn main() -> Result<()> {
             "epoch_length": 100,
         })),
         additional_accounts: vec![
-            GenesisAccount {
-                account_id: "alice.near".to_string(),
-                public_key: "ed25519:AzBN9XwQDRuLvGvor2JnMitkRxBxn2TLY4yEM3othKUF".to_string(),
-                private_key: "ed25519:5byt6y8h1uuHwkr2ozfN5gt8xGiHujpcT5KyNhZpG62BrnU51sMQk5eTVNwWp7RRiMgKHp7W1jrByxLCr2apXNGB".to_string(),
-                balance: NearToken::from_near(1000).as_yoctonear(),
-            },
+            GenesisAccount::new(
+                "alice.near",
+                "ed25519:AzBN9XwQDRuLvGvor2JnMitkRxBxn2TLY4yEM3othKUF",
+                "ed25519:5byt6y8h1uuHwkr2ozfN5gt8xGiHujpcT5KyNhZpG62BrnU51sMQk5eTVNwWp7RRiMgKHp7W1jrByxLCr2apXNGB",
+                NearToken::from_near(1000).as_yoctonear(),
+            ),
         ],
         ..Default::default()
     };
@@ -0,0 +1,392 @@
+//! Fetching and unpacking of the `near-sandbox` release artifact.
+//!
+//! Downloads are retried with exponential backoff over an ordered list of mirrors,
+//! and each attempt is bounded by a timeout so a stalled connection can't hang the
+//! test run forever.
+
+use std::ffi::OsStr;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+
+/// Base URL of the official nearcore build artifacts.
+pub const DEFAULT_MIRROR: &str =
+    "https://s3-us-west-1.amazonaws.com/build.nearprotocol.com/nearcore";
+
+/// Location of the artifact relative to a mirror base URL.
+const MIRROR_ARTIFACT_PATH: &str = "{platform}/{version}/near-sandbox.tar.gz";
+
+const DEFAULT_RETRIES: u32 = 3;
+const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(10);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Settings controlling how the sandbox binary gets downloaded.
+///
+/// [`DownloadConfig::from_env`] picks up overrides from the environment:
+/// * `NEAR_SANDBOX_CACHE_DIR` - root directory of the binary cache
+/// * `SANDBOX_ARTIFACT_URL` - artifact URL template, see [`DownloadConfig::artifact_url`]
+/// * `NEAR_SANDBOX_DOWNLOAD_MIRRORS` - comma separated list of mirror base URLs
+/// * `NEAR_SANDBOX_DOWNLOAD_RETRIES` - number of retries after the first attempt
+/// * `NEAR_SANDBOX_DOWNLOAD_TIMEOUT_SECS` - timeout of a single attempt
+#[derive(Debug, Clone)]
+pub struct DownloadConfig {
+    /// Root directory of the binary cache. Every version gets installed into its own
+    /// `near-sandbox-{version}` directory below it. Defaults to [`default_cache_dir`].
+    pub cache_dir: PathBuf,
+    /// Full URL of the artifact tarball, taking precedence over `mirrors` when set.
+    /// The `{version}` and `{platform}` placeholders get replaced by the requested
+    /// version and the current platform (e.g. `Linux-x86_64`), so a single template
+    /// can serve every version:
+    /// `https://mirror.internal/near-sandbox/{platform}/{version}/near-sandbox.tar.gz`
+    pub artifact_url: Option<String>,
+    /// Mirror base URLs, tried in order. The artifact is expected at
+    /// `{mirror}/{platform}/{version}/near-sandbox.tar.gz`.
+    pub mirrors: Vec<String>,
+    /// How many more times all mirrors get retried after the first pass failed.
+    pub retries: u32,
+    /// Delay before the first retry. Doubled after every failed pass.
+    pub initial_backoff: Duration,
+    /// Upper bound for the delay between two passes.
+    pub max_backoff: Duration,
+    /// Timeout for a single download attempt, including connecting and reading the body.
+    pub timeout: Duration,
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        Self {
+            cache_dir: default_cache_dir(),
+            artifact_url: None,
+            mirrors: vec![DEFAULT_MIRROR.to_string()],
+            retries: DEFAULT_RETRIES,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+impl DownloadConfig {
+    /// Default configuration with overrides taken from the environment variables.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut config = Self::default();
+
+        if let Some(dir) = std::env::var_os("NEAR_SANDBOX_CACHE_DIR") {
+            config.cache_dir = PathBuf::from(dir);
+        }
+        if let Ok(url) = std::env::var("SANDBOX_ARTIFACT_URL") {
+            config.artifact_url = Some(url);
+        }
+        if let Ok(mirrors) = std::env::var("NEAR_SANDBOX_DOWNLOAD_MIRRORS") {
+            let mirrors: Vec<String> = mirrors
+                .split(',')
+                .map(str::trim)
+                .filter(|mirror| !mirror.is_empty())
+                .map(String::from)
+                .collect();
+            if !mirrors.is_empty() {
+                config.mirrors = mirrors;
+            }
+        }
+        if let Ok(retries) = std::env::var("NEAR_SANDBOX_DOWNLOAD_RETRIES") {
+            config.retries = retries
+                .parse()
+                .context("Failed to parse NEAR_SANDBOX_DOWNLOAD_RETRIES")?;
+        }
+        if let Ok(secs) = std::env::var("NEAR_SANDBOX_DOWNLOAD_TIMEOUT_SECS") {
+            let secs = secs
+                .parse()
+                .context("Failed to parse NEAR_SANDBOX_DOWNLOAD_TIMEOUT_SECS")?;
+            config.timeout = Duration::from_secs(secs);
+        }
+
+        Ok(config)
+    }
+
+    /// All the URLs the artifact for `version` can be fetched from, in the order they should be tried.
+    ///
+    /// `platform` is only required when one of the templates references it, which allows
+    /// custom artifact URLs to serve platforms without official builds.
+    pub(crate) fn artifact_urls(
+        &self,
+        version: &str,
+        platform: Option<&str>,
+    ) -> anyhow::Result<Vec<String>> {
+        let templates = match &self.artifact_url {
+            Some(template) => vec![template.clone()],
+            None => self
+                .mirrors
+                .iter()
+                .map(|mirror| format!("{}/{MIRROR_ARTIFACT_PATH}", mirror.trim_end_matches('/')))
+                .collect(),
+        };
+
+        templates
+            .iter()
+            .map(|template| {
+                if !template.contains("{platform}") {
+                    return Ok(render_artifact_url(template, version, ""));
+                }
+                let platform = platform.ok_or_else(|| {
+                    anyhow!("Unsupported platform: only linux-x86 and darwin-arm are supported")
+                })?;
+                Ok(render_artifact_url(template, version, platform))
+            })
+            .collect()
+    }
+
+    /// Download the first artifact that can be fetched out of `urls`, retrying the whole
+    /// list with exponential backoff until the retries are exhausted.
+    pub(crate) fn fetch(&self, urls: &[String]) -> anyhow::Result<Vec<u8>> {
+        if urls.is_empty() {
+            anyhow::bail!("no URLs to download near-sandbox from");
+        }
+
+        let agent = ureq::AgentBuilder::new().timeout(self.timeout).build();
+        let mut backoff = self.initial_backoff;
+        let mut errors = Vec::new();
+
+        for attempt in 0..=self.retries {
+            if attempt > 0 {
+                tracing::warn!(
+                    target: "sandbox",
+                    "near-sandbox download failed, retrying in {:?} (attempt {}/{})",
+                    backoff,
+                    attempt,
+                    self.retries,
+                );
+                std::thread::sleep(backoff);
+                backoff = std::cmp::min(backoff * 2, self.max_backoff);
+            }
+
+            for url in urls {
+                match fetch_once(&agent, url) {
+                    Ok(bytes) => return Ok(bytes),
+                    Err(err) => errors.push(format!("{url}: {err:#}")),
+                }
+            }
+        }
+
+        Err(anyhow!(
+            "unable to download near-sandbox after {} attempt(s):\n  {}",
+            self.retries + 1,
+            errors.join("\n  ")
+        ))
+    }
+}
+
+/// The directory sandbox binaries are cached in unless configured otherwise:
+/// * `$HOME/.near` with the `global_install` feature enabled,
+/// * `$XDG_CACHE_HOME/near-sandbox`, falling back to `$HOME/.cache/near-sandbox` on Linux
+///   and `$HOME/Library/Caches/near-sandbox` on MacOS,
+/// * `$OUT_DIR/.near` of this crate if no home directory can be found.
+pub fn default_cache_dir() -> PathBuf {
+    let home = home::home_dir();
+
+    if cfg!(feature = "global_install") {
+        if let Some(home) = home {
+            return home.join(".near");
+        }
+    }
+
+    let cache_home = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| {
+            let home = home?;
+            if cfg!(target_os = "macos") {
+                Some(home.join("Library").join("Caches"))
+            } else {
+                Some(home.join(".cache"))
+            }
+        });
+
+    match cache_home {
+        Some(cache_home) => cache_home.join("near-sandbox"),
+        None => PathBuf::from(env!("OUT_DIR")).join(".near"),
+    }
+}
+
+/// Substitute the `{version}` and `{platform}` placeholders of an artifact URL template.
+/// Templates without placeholders are returned verbatim.
+pub fn render_artifact_url(template: &str, version: &str, platform: &str) -> String {
+    template
+        .replace("{version}", version)
+        .replace("{platform}", platform)
+}
+
+fn fetch_once(agent: &ureq::Agent, url: &str) -> anyhow::Result<Vec<u8>> {
+    let response = match agent.get(url).call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(code, _)) => {
+            anyhow::bail!("received a bad HTTP status code ({code})")
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    let len = response
+        .header("Content-Length")
+        .and_then(|len| len.parse().ok());
+    let mut bytes = Vec::with_capacity(len.unwrap_or(0));
+    response.into_reader().read_to_end(&mut bytes)?;
+
+    if let Some(len) = len {
+        if bytes.len() != len {
+            anyhow::bail!("expected {len} bytes but only received {}", bytes.len());
+        }
+    }
+
+    Ok(bytes)
+}
+
+/// Extract the `near-sandbox` binary out of a gzipped tarball into `dest`.
+pub(crate) fn unpack_binary(tarball: &[u8], dest: &Path) -> anyhow::Result<()> {
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(tarball));
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.path()?.file_name() == Some(OsStr::new("near-sandbox")) {
+            let expected = entry.header().size()?;
+            entry.unpack(dest)?;
+
+            let actual = std::fs::metadata(dest)?.len();
+            if actual != expected {
+                anyhow::bail!("unpacked {actual} bytes of near-sandbox, expected {expected}");
+            }
+            return Ok(());
+        }
+    }
+
+    Err(anyhow!(
+        "the tarball was missing the near-sandbox executable"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::time::Instant;
+
+    use super::*;
+
+    /// Minimal HTTP server answering the `n`-th request with `responses(path, n)`, a status code
+    /// and body. Returns its base URL and the paths of the requests it received, in order.
+    fn serve(
+        responses: impl Fn(&str, usize) -> (u16, &'static str) + Send + 'static,
+    ) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let received = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                // Skip the headers, the requests have no body.
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+
+                let path = request_line.split(' ').nth(1).unwrap().to_string();
+                let n = {
+                    let mut received = received.lock().unwrap();
+                    received.push(path.clone());
+                    received.len() - 1
+                };
+                let (status, body) = responses(&path, n);
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {status} X\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+            }
+        });
+
+        (base, requests)
+    }
+
+    fn config(retries: u32, backoff: Duration) -> DownloadConfig {
+        DownloadConfig {
+            retries,
+            initial_backoff: backoff,
+            max_backoff: backoff * 2,
+            timeout: Duration::from_secs(5),
+            ..DownloadConfig::default()
+        }
+    }
+
+    #[test]
+    fn fetch_tries_mirrors_in_order_before_retrying() {
+        // The primary mirror is down, the secondary only serves the artifact from the second
+        // pass on.
+        let (base, requests) = serve(|path, n| match path {
+            "/secondary" if n >= 3 => (200, "tarball"),
+            "/secondary" => (404, ""),
+            _ => (500, ""),
+        });
+        let urls = vec![format!("{base}/primary"), format!("{base}/secondary")];
+
+        let bytes = config(3, Duration::from_millis(10)).fetch(&urls).unwrap();
+
+        assert_eq!(bytes, b"tarball");
+        assert_eq!(
+            *requests.lock().unwrap(),
+            ["/primary", "/secondary", "/primary", "/secondary"]
+        );
+    }
+
+    #[test]
+    fn fetch_backs_off_between_passes() {
+        let (base, requests) = serve(|_, _| (503, ""));
+        let urls = vec![format!("{base}/artifact")];
+
+        // Backoff of 50ms, 100ms and then capped at 100ms.
+        let start = Instant::now();
+        let err = config(3, Duration::from_millis(50))
+            .fetch(&urls)
+            .unwrap_err();
+
+        assert!(start.elapsed() >= Duration::from_millis(250));
+        assert_eq!(requests.lock().unwrap().len(), 4);
+        let err = format!("{err:#}");
+        assert!(err.contains("after 4 attempt(s)"), "{}", err);
+        assert!(err.contains("bad HTTP status code (503)"), "{}", err);
+    }
+
+    #[test]
+    fn artifact_url_takes_precedence_over_mirrors() {
+        let config = DownloadConfig {
+            artifact_url: Some("https://example.com/{version}/sandbox.tar.gz".to_string()),
+            ..DownloadConfig::default()
+        };
+        assert_eq!(
+            config.artifact_urls("2.6.3", None).unwrap(),
+            ["https://example.com/2.6.3/sandbox.tar.gz"]
+        );
+
+        let config = DownloadConfig {
+            mirrors: vec![
+                "https://a.example.com/".to_string(),
+                "https://b.example.com".to_string(),
+            ],
+            ..DownloadConfig::default()
+        };
+        assert_eq!(
+            config.artifact_urls("2.6.3", Some("Linux-x86_64")).unwrap(),
+            [
+                "https://a.example.com/Linux-x86_64/2.6.3/near-sandbox.tar.gz",
+                "https://b.example.com/Linux-x86_64/2.6.3/near-sandbox.tar.gz",
+            ]
+        );
+        assert!(config.artifact_urls("2.6.3", None).is_err());
+    }
+}
@@ -13,11 +13,20 @@
 
 use std::fs::File;
 use std::io::{BufReader, Write};
-use std::path::Path;
-use std::str::FromStr;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+use base64::Engine;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use sha2::Digest;
+
+use super::config_keys::{self, ConfigKeyCheck};
+use super::node_config::NodeConfig;
+use super::presets::GenesisPreset;
+use super::shards::ShardLayout;
+use super::{genesis, AccessKey, AccessKeyPermission};
 
 pub const DEFAULT_GENESIS_ACCOUNT: &str = "sandbox";
 pub const DEFAULT_GENESIS_ACCOUNT_PRIVATE_KEY: &str = "ed25519:3tgdk2wPraJzT4nsTuf86UX41xgPNk3MHnq8epARMdBNs29AFEztAuaQ7iHddDfXG9F2RzV1XNQYgJyAyoW51UBB";
@@ -25,23 +34,206 @@ pub const DEFAULT_GENESIS_ACCOUNT_PUBLIC_KEY: &str =
     "ed25519:5BGSaf6YjVm7565VzWQHNxoyEjwr3jUpRJSGjREvU9dB";
 pub const DEFAULT_GENESIS_ACCOUNT_BALANCE: u128 = 10_000u128 * 10u128.pow(24);
 
+/// How long the block rate gets measured by [`SandboxConfig::fast_block_production`].
+const DEFAULT_BLOCK_RATE_WINDOW: Duration = Duration::from_secs(1);
+
+// Storage usage of genesis records, following nearcore's `StorageUsageConfig`.
+const NUM_BYTES_ACCOUNT: u64 = 100;
+const NUM_EXTRA_BYTES_RECORD: u64 = 40;
+
 /// Genesis account configuration
+///
+/// Fields get added over time, so outside of this crate accounts are built with
+/// [`GenesisAccount::new`] or from [`Default`], and then adjusted field by field.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[non_exhaustive]
 pub struct GenesisAccount {
     pub account_id: String,
+    /// Public key of the account's primary full access key, which starts with nonce 0.
     pub public_key: String,
     pub private_key: String,
     pub balance: u128,
+    /// Amount locked for staking, on top of `balance`. The node requires every account locking
+    /// a balance to be one of the genesis `validators` staking exactly that amount, so such
+    /// accounts have to be added to them through `additional_genesis`, which replaces the whole
+    /// list. Mismatches are rejected when the genesis is written.
+    #[serde(default)]
+    pub locked: u128,
+    /// Access keys the account has in addition to its primary key.
+    #[serde(default)]
+    pub additional_keys: Vec<GenesisAccessKey>,
+    /// Wasm code deployed to the account at genesis.
+    #[serde(default)]
+    pub code: Option<Vec<u8>>,
+}
+
+impl GenesisAccount {
+    /// An account with the given primary key pair and balance, without locked balance,
+    /// additional keys or code.
+    pub fn new(
+        account_id: impl Into<String>,
+        public_key: impl Into<String>,
+        private_key: impl Into<String>,
+        balance: u128,
+    ) -> Self {
+        Self {
+            account_id: account_id.into(),
+            public_key: public_key.into(),
+            private_key: private_key.into(),
+            balance,
+            locked: 0,
+            additional_keys: Vec::new(),
+            code: None,
+        }
+    }
+
+    /// The primary key followed by the additional keys of the account.
+    fn keys(&self) -> impl Iterator<Item = GenesisAccessKey> + '_ {
+        std::iter::once(GenesisAccessKey::full_access(self.public_key.clone(), None))
+            .chain(self.additional_keys.iter().cloned())
+    }
+
+    /// Bytes of storage the account uses at genesis, computed the same way the node does
+    /// from its account record, access keys and code.
+    pub fn storage_usage(&self) -> u64 {
+        let keys: u64 = self.keys().map(|key| key.storage_usage()).sum();
+        let code = self.code.as_ref().map_or(0, |code| code.len() as u64);
+        NUM_BYTES_ACCOUNT + keys + code
+    }
+
+    /// Base58 sha256 hash of the deployed code, or the all-zero hash if there's none.
+    pub fn code_hash(&self) -> String {
+        let hash: [u8; 32] = match &self.code {
+            Some(code) => sha2::Sha256::digest(code).into(),
+            None => [0; 32],
+        };
+        bs58::encode(hash).into_string()
+    }
+
+    /// The genesis records of this account: the account itself, its keys and its code.
+    fn records(&self) -> Vec<Value> {
+        let mut records = vec![serde_json::json!({
+            "Account": {
+                "account_id": self.account_id,
+                "account": {
+                    "amount": self.balance.to_string(),
+                    "locked": self.locked.to_string(),
+                    "code_hash": self.code_hash(),
+                    "storage_usage": self.storage_usage(),
+                }
+            }
+        })];
+        records.extend(self.keys().map(|key| key.record(&self.account_id)));
+        if let Some(code) = &self.code {
+            records.push(serde_json::json!({
+                "Contract": {
+                    "account_id": self.account_id,
+                    "code": base64::engine::general_purpose::STANDARD.encode(code),
+                }
+            }));
+        }
+        records
+    }
+}
+
+/// An access key added to a [`GenesisAccount`] at genesis.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenesisAccessKey {
+    pub public_key: String,
+    /// Private key of the pair, only kept around to sign with it later, e.g. via
+    /// [`Signer::from_secret_key`](super::Signer::from_secret_key).
+    pub private_key: Option<String>,
+    pub access_key: AccessKey,
+}
+
+impl GenesisAccessKey {
+    /// A full access key with nonce 0.
+    pub fn full_access(public_key: impl Into<String>, private_key: Option<String>) -> Self {
+        Self {
+            public_key: public_key.into(),
+            private_key,
+            access_key: AccessKey {
+                nonce: 0,
+                permission: AccessKeyPermission::FullAccess,
+            },
+        }
+    }
+
+    /// A key with nonce 0 that may only call `method_names` on `receiver_id`, or any of its
+    /// methods if empty, spending at most `allowance` yoctoNEAR on gas (unlimited if `None`).
+    pub fn function_call(
+        public_key: impl Into<String>,
+        private_key: Option<String>,
+        receiver_id: impl Into<String>,
+        method_names: Vec<String>,
+        allowance: Option<u128>,
+    ) -> Self {
+        Self {
+            public_key: public_key.into(),
+            private_key,
+            access_key: AccessKey {
+                nonce: 0,
+                permission: AccessKeyPermission::FunctionCall {
+                    allowance,
+                    receiver_id: receiver_id.into(),
+                    method_names,
+                },
+            },
+        }
+    }
+
+    /// Start the key at `nonce` instead of 0.
+    pub fn with_nonce(mut self, nonce: u64) -> Self {
+        self.access_key.nonce = nonce;
+        self
+    }
+
+    fn storage_usage(&self) -> u64 {
+        let access_key = borsh::to_vec(&self.access_key).expect("serializing to vec never fails");
+        // Borsh of the public key is its type followed by the key bytes.
+        let (key_type, _) = genesis::split_key(&self.public_key);
+        let public_key = 1 + genesis::public_key_len(key_type).unwrap_or(32) as u64;
+        NUM_EXTRA_BYTES_RECORD + public_key + access_key.len() as u64
+    }
+
+    /// The genesis `AccessKey` record of this key for `account_id`.
+    fn record(&self, account_id: &str) -> Value {
+        let permission = match &self.access_key.permission {
+            AccessKeyPermission::FullAccess => Value::from("FullAccess"),
+            AccessKeyPermission::FunctionCall {
+                allowance,
+                receiver_id,
+                method_names,
+            } => serde_json::json!({
+                "FunctionCall": {
+                    "allowance": allowance.map(|allowance| allowance.to_string()),
+                    "receiver_id": receiver_id,
+                    "method_names": method_names,
+                }
+            }),
+        };
+
+        serde_json::json!({
+            "AccessKey": {
+                "account_id": account_id,
+                "public_key": self.public_key,
+                "access_key": {
+                    "nonce": self.access_key.nonce,
+                    "permission": permission,
+                }
+            }
+        })
+    }
 }
 
 impl Default for GenesisAccount {
     fn default() -> Self {
-        GenesisAccount {
-            account_id: DEFAULT_GENESIS_ACCOUNT.to_string(),
-            public_key: DEFAULT_GENESIS_ACCOUNT_PUBLIC_KEY.to_string(),
-            private_key: DEFAULT_GENESIS_ACCOUNT_PRIVATE_KEY.to_string(),
-            balance: DEFAULT_GENESIS_ACCOUNT_BALANCE,
-        }
+        GenesisAccount::new(
+            DEFAULT_GENESIS_ACCOUNT,
+            DEFAULT_GENESIS_ACCOUNT_PUBLIC_KEY,
+            DEFAULT_GENESIS_ACCOUNT_PRIVATE_KEY,
+            DEFAULT_GENESIS_ACCOUNT_BALANCE,
+        )
     }
 }
 
@@ -52,12 +244,135 @@ pub struct SandboxConfig {
     pub max_payload_size: Option<usize>,
     /// Maximum number of open files
     pub max_open_files: Option<usize>,
-    /// Additional JSON configuration to merge with the default config
+    /// Run the node as archival node, which keeps the full history instead of garbage
+    /// collecting blocks, chunks and state older than a few epochs. This makes the RPC answer
+    /// queries about any height since genesis:
+    /// - `query` (`view_account`, `view_state`, `view_access_key`, `call_function`, ...)
+    ///   with an old `block_id`
+    /// - `block` and `chunk` by old height or hash
+    /// - `tx` and `EXPERIMENTAL_tx_status` for old transactions
+    /// - `EXPERIMENTAL_changes` and `EXPERIMENTAL_changes_in_block` at old blocks
+    /// - `validators` for past epochs
+    ///
+    /// Turns on `archive` and `save_trie_changes` of `node_config`, so setting either of them
+    /// to `false` there is rejected.
+    #[serde(default)]
+    pub archival: bool,
+    /// Frequently used settings of the node's config.json
+    #[serde(default)]
+    pub node_config: NodeConfig,
+    /// Additional JSON configuration to merge with the default config, applied after
+    /// `node_config`
     pub additional_config: Option<Value>,
     /// Additional accounts to add to the genesis
     pub additional_accounts: Vec<GenesisAccount>,
-    /// Additional JSON configuration to merge with the genesis
+    /// Split the accounts into multiple shards, all of which the node tracks. Single shard if
+    /// not set. `node_config.tracked_shards` defaults to all the shards and is rejected if it
+    /// leaves any of them out.
+    pub shard_layout: Option<ShardLayout>,
+    /// Genesis parameters like protocol version, gas limit, gas prices and epoch length to use
+    /// instead of the ones of `init --fast`.
+    pub genesis_preset: Option<GenesisPreset>,
+    /// Additional JSON configuration to merge with the genesis, applied after `genesis_preset`
     pub additional_genesis: Option<Value>,
+    /// Address the RPC server binds to, `127.0.0.1` by default. Use `0.0.0.0` or `::` to
+    /// listen on all interfaces, e.g. when the sandbox runs inside a container.
+    pub rpc_bind_addr: Option<IpAddr>,
+    /// Address the network server binds to, `127.0.0.1` by default.
+    pub net_bind_addr: Option<IpAddr>,
+    /// Fixed RPC port. A random unused port is picked if not set.
+    pub rpc_port: Option<u16>,
+    /// Fixed network port. A random unused port is picked if not set.
+    pub net_port: Option<u16>,
+    /// Parse the node's log output and re-emit it as `tracing` events with the
+    /// `sandbox::node` target instead of letting it go to the terminal.
+    /// The node's log filter is then left at its default unless `NEAR_SANDBOX_LOG` is set.
+    #[serde(default)]
+    pub forward_logs: bool,
+    /// Log filter of the node, passed as `RUST_LOG`, e.g. `near=info,runtime=debug`.
+    /// Defaults to `NEAR_SANDBOX_LOG` if the sandbox logs are enabled with
+    /// `NEAR_ENABLE_SANDBOX_LOG=1` or forwarded, and to only showing errors otherwise.
+    pub log_filter: Option<String>,
+    /// Log style of the node, passed as `RUST_LOG_STYLE`. Defaults to `NEAR_SANDBOX_LOG_STYLE`.
+    pub log_style: Option<String>,
+    /// Also export the genesis account keys in the near-cli credentials layout into
+    /// `<credentials_dir>/<network>/<account_id>.json`, e.g. `~/.near-credentials` as returned
+    /// by [`default_credentials_dir`](crate::high_level::default_credentials_dir).
+    pub credentials_dir: Option<PathBuf>,
+    /// Network the keys are exported under, `sandbox` by default.
+    pub credentials_network: Option<String>,
+    /// Whether to check `additional_config` for keys nearcore doesn't know, e.g. typos like
+    /// `limit_config`, and whether to warn or fail if there are any. Off by default.
+    #[serde(default)]
+    pub config_key_check: ConfigKeyCheck,
+    /// Measure the rate at which the node produces blocks over this long after startup,
+    /// reported by `Sandbox::blocks_per_second`.
+    pub measure_block_rate: Option<Duration>,
+}
+
+// non-exhaustive list of targets to suppress, since choosing a default LogLevel
+// does nothing in this case, since nearcore seems to be overriding it somehow:
+const SUPPRESSED_LOG_FILTER: &str = "near=error,stats=error,network=error";
+
+impl SandboxConfig {
+    /// The default genesis account followed by the additional accounts.
+    fn genesis_accounts(&self) -> Vec<GenesisAccount> {
+        let mut accounts = vec![GenesisAccount::default()];
+        accounts.extend(self.additional_accounts.clone());
+        accounts
+    }
+
+    /// Config for tests waiting on blocks or finality: the node produces blocks as fast as it
+    /// reliably can, see [`NodeConfig::fast_block_production`], and the resulting block rate is
+    /// measured during startup.
+    pub fn fast_block_production() -> Self {
+        Self {
+            node_config: NodeConfig::fast_block_production(),
+            measure_block_rate: Some(DEFAULT_BLOCK_RATE_WINDOW),
+            ..Self::default()
+        }
+    }
+
+    /// Check the genesis accounts for invalid or duplicate account IDs and malformed or
+    /// duplicate keys, reporting all problems found in a single error. This also happens
+    /// when the sandbox gets started, which additionally rejects accounts clashing with the
+    /// ones `init` creates, like `test.near`, as those are only known then.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        genesis::check(genesis::validate_accounts(&self.genesis_accounts()))
+    }
+
+    /// Environment variables configuring the logs of this sandbox's node.
+    ///
+    /// neard-sandbox logs are turned off by default. Users can turn them back on with
+    /// NEAR_ENABLE_SANDBOX_LOG=1 and specify further parameters with the custom
+    /// NEAR_SANDBOX_LOG for higher levels of specificity. NEAR_SANDBOX_LOG args
+    /// will be forward into RUST_LOG environment variable as to not conflict
+    /// with similar named log targets.
+    pub(crate) fn log_vars(&self) -> Vec<(String, String)> {
+        let logs_enabled = self.forward_logs
+            || std::env::var("NEAR_ENABLE_SANDBOX_LOG").is_ok_and(|val| val != "0");
+
+        let filter = self.log_filter.clone().or_else(|| {
+            if logs_enabled {
+                std::env::var("NEAR_SANDBOX_LOG").ok()
+            } else {
+                Some(SUPPRESSED_LOG_FILTER.to_string())
+            }
+        });
+        let style = self
+            .log_style
+            .clone()
+            .or_else(|| std::env::var("NEAR_SANDBOX_LOG_STYLE").ok());
+
+        let mut vars = Vec::new();
+        if let Some(filter) = filter {
+            vars.push(("RUST_LOG".into(), filter));
+        }
+        if let Some(style) = style {
+            vars.push(("RUST_LOG_STYLE".into(), style));
+        }
+        vars
+    }
 }
 
 /// Overwrite the $home_dir/config.json file over a set of entries. `value` will be used per (key, value) pair
@@ -92,6 +407,45 @@ where
 }
 
 /// Set extra configs for the sandbox with custom configuration.
+/// `config.node_config` together with the settings `archival` and `shard_layout` imply,
+/// failing if they contradict each other.
+fn node_config(config: &SandboxConfig) -> anyhow::Result<NodeConfig> {
+    let mut node_config = config.node_config.clone();
+
+    if config.archival {
+        // Archival nodes never garbage collect; they need the trie changes of every block to
+        // serve state at old heights.
+        for (key, value) in [
+            ("archive", &mut node_config.archive),
+            ("save_trie_changes", &mut node_config.save_trie_changes),
+        ] {
+            if *value == Some(false) {
+                anyhow::bail!("`archival` conflicts with `node_config.{key}` set to false");
+            }
+            *value = Some(true);
+        }
+    }
+
+    if let Some(shard_layout) = &config.shard_layout {
+        let num_shards = shard_layout.num_shards()?;
+        match &node_config.tracked_shards {
+            Some(tracked_shards) => {
+                if let Some(shard_id) =
+                    (0..num_shards).find(|shard_id| !tracked_shards.contains(shard_id))
+                {
+                    anyhow::bail!(
+                        "`node_config.tracked_shards` leaves shard {shard_id} of `shard_layout` \
+                         untracked, the single sandbox node has to track all {num_shards} shards"
+                    );
+                }
+            }
+            None => node_config.tracked_shards = Some((0..num_shards).collect()),
+        }
+    }
+
+    Ok(node_config)
+}
+
 pub(crate) fn set_sandbox_configs_with_config(
     home_dir: impl AsRef<Path>,
     config: &SandboxConfig,
@@ -117,8 +471,15 @@ pub(crate) fn set_sandbox_configs_with_config(
         }
     });
 
+    json_patch::merge(&mut json_config, &node_config(config)?.to_json());
+
     // Merge any additional config provided by the user
     if let Some(additional_config) = &config.additional_config {
+        if config.config_key_check != ConfigKeyCheck::Off {
+            let config_file = File::open(home_dir.as_ref().join("config.json"))?;
+            let known: Value = serde_json::from_reader(BufReader::new(config_file))?;
+            config_keys::check(config.config_key_check, &known, additional_config)?;
+        }
         json_patch::merge(&mut json_config, additional_config);
     }
 
@@ -129,32 +490,14 @@ pub(crate) fn set_sandbox_configs_with_config(
 /// where value can also be another dict. This recursively sets all entry in `value` dict to the config
 /// dict, and saves back into `home_dir` at the end of the day.
 fn overwrite_genesis(home_dir: impl AsRef<Path>, config: &SandboxConfig) -> anyhow::Result<()> {
+    config.validate()?;
+    let accounts_to_add = config.genesis_accounts();
+
     let home_dir = home_dir.as_ref();
     let config_file = File::open(home_dir.join("genesis.json"))?;
     let config_reader = BufReader::new(config_file);
     let mut genesis: Value = serde_json::from_reader(config_reader)?;
     let genesis_obj = genesis.as_object_mut().expect("expected to be object");
-    let mut total_supply = u128::from_str(
-        genesis_obj
-            .get_mut("total_supply")
-            .expect("expected exist total_supply")
-            .as_str()
-            .unwrap_or_default(),
-    )
-    .unwrap_or_default();
-
-    let mut accounts_to_add = vec![GenesisAccount::default()];
-
-    accounts_to_add.extend(config.additional_accounts.clone());
-
-    for account in &accounts_to_add {
-        total_supply += account.balance;
-    }
-
-    genesis_obj.insert(
-        "total_supply".to_string(),
-        Value::String(total_supply.to_string()),
-    );
 
     let records = genesis_obj
         .get_mut("records")
@@ -162,38 +505,40 @@ fn overwrite_genesis(home_dir: impl AsRef<Path>, config: &SandboxConfig) -> anyh
     let records_array = records.as_array_mut().expect("expected to be array");
 
     for account in &accounts_to_add {
-        records_array.push(serde_json::json!(
-            {
-                "Account": {
-                    "account_id": account.account_id,
-                    "account": {
-                    "amount": account.balance.to_string(),
-                    "locked": "0",
-                    "code_hash": "11111111111111111111111111111111",
-                    "storage_usage": 182
-                    }
-                }
-            }
-        ));
-
-        records_array.push(serde_json::json!(
-            {
-                "AccessKey": {
-                    "account_id": account.account_id,
-                    "public_key": account.public_key,
-                    "access_key": {
-                    "nonce": 0,
-                    "permission": "FullAccess"
-                    }
-                }
-            }
-        ));
+        records_array.extend(account.records());
+    }
+
+    if let Some(shard_layout) = &config.shard_layout {
+        shard_layout.apply(&mut genesis)?;
+    }
+
+    if let Some(preset) = config.genesis_preset {
+        // `init` starts the chain at the newest protocol version the binary supports.
+        let latest_protocol_version = genesis["protocol_version"]
+            .as_u64()
+            .ok_or_else(|| anyhow::anyhow!("genesis.json has no `protocol_version`"))?;
+        json_patch::merge(&mut genesis, &preset.to_json(latest_protocol_version));
     }
 
     if let Some(additional_genesis) = &config.additional_genesis {
         json_patch::merge(&mut genesis, additional_genesis);
     }
 
+    // Checked on the final records, as `init` and `additional_genesis` contribute to them too.
+    let mut problems = Vec::new();
+    let total_supply = match genesis["records"].as_array() {
+        Some(records) => genesis::check_records(records, &mut problems),
+        None => {
+            problems.push("`records` is not an array".to_string());
+            0
+        }
+    };
+    if let Some(records) = genesis["records"].as_array() {
+        genesis::validate_stakes(records, &genesis["validators"], &mut problems);
+    }
+    genesis::check(problems)?;
+    genesis["total_supply"] = Value::String(total_supply.to_string());
+
     let config_file = File::create(home_dir.join("genesis.json"))?;
     serde_json::to_writer(config_file, &genesis)?;
     Ok(())
@@ -234,10 +579,151 @@ pub fn set_sandbox_genesis_with_config(
 ) -> anyhow::Result<()> {
     overwrite_genesis(&home_dir, config)?;
 
-    let mut all_accounts = vec![GenesisAccount::default()];
-    all_accounts.extend(config.additional_accounts.clone());
+    let all_accounts = config.genesis_accounts();
 
     save_account_keys(&home_dir, &all_accounts)?;
 
+    if let Some(dir) = &config.credentials_dir {
+        let network = config
+            .credentials_network
+            .as_deref()
+            .unwrap_or(super::DEFAULT_CREDENTIALS_NETWORK);
+        super::export_credentials(dir, network, &all_accounts)?;
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_account_uses_the_storage_of_a_single_full_access_key() {
+        // The value that used to be hard-coded into every record.
+        assert_eq!(GenesisAccount::default().storage_usage(), 182);
+    }
+
+    #[test]
+    fn keys_and_code_add_to_the_storage_usage() {
+        let mut account = GenesisAccount {
+            additional_keys: vec![GenesisAccessKey::function_call(
+                DEFAULT_GENESIS_ACCOUNT_PUBLIC_KEY,
+                None,
+                "contract.sandbox",
+                vec!["get".to_string(), "set".to_string()],
+                Some(1),
+            )],
+            ..GenesisAccount::default()
+        };
+        // Borsh of the access key: nonce 8, permission tag 1, allowance 1 + 16, receiver
+        // 4 + 16 and method names 4 + 2 * (4 + 3).
+        let function_call_key = 40 + 33 + (8 + 1 + 17 + 20 + 18);
+        assert_eq!(account.storage_usage(), 182 + function_call_key);
+
+        account.code = Some(vec![0; 1000]);
+        assert_eq!(account.storage_usage(), 182 + function_call_key + 1000);
+
+        // secp256k1 public keys take 64 instead of 32 bytes.
+        let secp256k1 = format!("secp256k1:{}", bs58::encode([7; 64]).into_string());
+        account.additional_keys = vec![GenesisAccessKey::full_access(secp256k1, None)];
+        assert_eq!(account.storage_usage(), 182 + (40 + 65 + 9) + 1000);
+    }
+
+    #[test]
+    fn records_carry_the_code_and_its_hash() {
+        let mut account = GenesisAccount::default();
+        assert_eq!(account.code_hash(), "11111111111111111111111111111111");
+        assert_eq!(account.records().len(), 2);
+
+        account.code = Some(b"\0asm\x01\0\0\0".to_vec());
+        let records = account.records();
+        assert_eq!(records.len(), 3);
+        assert_eq!(
+            records[0]["Account"]["account"]["code_hash"],
+            "AwLEfgaHQguPVVLGUV9Sf5QKGrMMMr2N6MVSjBj9dJAh"
+        );
+        assert_eq!(records[0]["Account"]["account"]["storage_usage"], 182 + 8);
+        assert_eq!(
+            records[2],
+            serde_json::json!({
+                "Contract": { "account_id": DEFAULT_GENESIS_ACCOUNT, "code": "AGFzbQEAAAA=" }
+            })
+        );
+    }
+
+    #[test]
+    fn configs_written_before_the_new_fields_still_deserialize() {
+        let config: SandboxConfig = serde_json::from_value(serde_json::json!({
+            "max_payload_size": null,
+            "max_open_files": 4000,
+            "additional_config": null,
+            "additional_accounts": [{
+                "account_id": "alice.sandbox",
+                "public_key": DEFAULT_GENESIS_ACCOUNT_PUBLIC_KEY,
+                "private_key": DEFAULT_GENESIS_ACCOUNT_PRIVATE_KEY,
+                "balance": 1000,
+            }],
+            "additional_genesis": null,
+        }))
+        .unwrap();
+
+        assert_eq!(config.max_open_files, Some(4000));
+        assert!(!config.forward_logs);
+        assert!(!config.archival);
+        assert_eq!(config.config_key_check, ConfigKeyCheck::Off);
+        let account = &config.additional_accounts[0];
+        assert_eq!(account.locked, 0);
+        assert!(account.additional_keys.is_empty());
+        assert!(account.code.is_none());
+    }
+
+    #[test]
+    fn archival_and_shard_layout_extend_the_node_config() {
+        let config = SandboxConfig {
+            archival: true,
+            shard_layout: Some(ShardLayout::NumShards(3)),
+            node_config: NodeConfig {
+                gc_num_epochs_to_keep: Some(10),
+                ..NodeConfig::default()
+            },
+            ..SandboxConfig::default()
+        };
+        assert_eq!(
+            node_config(&config).unwrap(),
+            NodeConfig {
+                tracked_shards: Some(vec![0, 1, 2]),
+                gc_num_epochs_to_keep: Some(10),
+                archive: Some(true),
+                save_trie_changes: Some(true),
+                ..NodeConfig::default()
+            }
+        );
+    }
+
+    #[test]
+    fn contradicting_node_config_is_rejected() {
+        let mut config = SandboxConfig {
+            archival: true,
+            ..SandboxConfig::default()
+        };
+        config.node_config.archive = Some(false);
+        assert_eq!(
+            node_config(&config).unwrap_err().to_string(),
+            "`archival` conflicts with `node_config.archive` set to false"
+        );
+
+        let mut config = SandboxConfig {
+            shard_layout: Some(ShardLayout::NumShards(3)),
+            ..SandboxConfig::default()
+        };
+        config.node_config.tracked_shards = Some(vec![0, 2]);
+        assert!(node_config(&config)
+            .unwrap_err()
+            .to_string()
+            .contains("leaves shard 1 of `shard_layout` untracked"));
+
+        config.node_config.tracked_shards = Some(vec![2, 1, 0]);
+        assert!(node_config(&config).is_ok());
+    }
+}
@@ -0,0 +1,221 @@
+//! Checking `additional_config` against the keys nearcore knows about.
+//!
+//! Merging JSON into config.json never fails, so a misspelled key like `limit_config` is
+//! silently ignored by the node. The freshly initialized config.json contains nearly every
+//! key nearcore understands, so anything not in there (or in the short list of optional keys
+//! nearcore leaves out by default) is most likely a typo.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// What to do about keys in `additional_config` that nearcore doesn't know.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConfigKeyCheck {
+    /// Merge the config without checking it.
+    #[default]
+    Off,
+    /// Log a warning for every unknown key.
+    Warn,
+    /// Fail to start the sandbox if there are unknown keys.
+    Deny,
+}
+
+/// Keys nearcore accepts but doesn't write into a freshly initialized config.json.
+const OPTIONAL_KEYS: &[&str] = &[
+    "cold_store",
+    "db_migration_snapshot_path",
+    "epoch_sync",
+    "expected_shutdown",
+    "rosetta_rpc",
+    "rpc.enable_debug_rpc",
+    "rpc.experimental_debug_pages_src_path",
+    "save_trie_changes",
+    "save_tx_outcomes",
+    "split_storage",
+    "state_sync",
+    "tracked_shard_schedule",
+    "tracked_shards_config",
+];
+
+/// Keys which are at most this far apart get suggested as replacement.
+fn max_distance(key: &str) -> usize {
+    (key.len() / 3).max(1)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                prev
+            } else {
+                1 + prev.min(row[j]).min(current)
+            };
+            prev = current;
+        }
+    }
+    row[b.len()]
+}
+
+fn is_optional(path: &str) -> bool {
+    OPTIONAL_KEYS
+        .iter()
+        .any(|key| path == *key || path.starts_with(&format!("{key}.")))
+}
+
+fn check_object(
+    known: &serde_json::Map<String, Value>,
+    value: &serde_json::Map<String, Value>,
+    prefix: &str,
+    problems: &mut Vec<String>,
+) {
+    for (key, value) in value {
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{prefix}.{key}")
+        };
+
+        match known.get(key) {
+            // Recurse only into sections, anything else is a value nearcore parses by itself.
+            Some(Value::Object(known)) => {
+                if let Value::Object(value) = value {
+                    check_object(known, value, &path, problems);
+                }
+            }
+            Some(_) => {}
+            None if is_optional(&path) => {}
+            None => {
+                let mut candidates: Vec<(usize, &String)> = known
+                    .keys()
+                    .map(|candidate| (edit_distance(key, candidate), candidate))
+                    .filter(|(distance, _)| *distance <= max_distance(key))
+                    .collect();
+                candidates.sort();
+
+                let mut problem = format!("unknown config key `{path}`");
+                if let Some((_, candidate)) = candidates.first() {
+                    problem.push_str(&format!(", did you mean `{candidate}`?"));
+                }
+                problems.push(problem);
+            }
+        }
+    }
+}
+
+/// Describe every key of `additional_config` that is neither in the freshly initialized
+/// config `known` nor one of the optional keys, with suggestions of close matches.
+fn unknown_keys(known: &Value, additional_config: &Value) -> Vec<String> {
+    let mut problems = Vec::new();
+    if let (Value::Object(known), Value::Object(additional_config)) = (known, additional_config) {
+        check_object(known, additional_config, "", &mut problems);
+    }
+    problems
+}
+
+/// Check `additional_config` against the config.json `known` according to `mode`.
+pub(crate) fn check(
+    mode: ConfigKeyCheck,
+    known: &Value,
+    additional_config: &Value,
+) -> anyhow::Result<()> {
+    if mode == ConfigKeyCheck::Off {
+        return Ok(());
+    }
+
+    let problems = unknown_keys(known, additional_config);
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    if mode == ConfigKeyCheck::Deny {
+        anyhow::bail!(
+            "invalid additional_config:\n  - {}",
+            problems.join("\n  - ")
+        );
+    }
+    for problem in problems {
+        tracing::warn!(target: "sandbox", "additional_config: {problem}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn known() -> Value {
+        json!({
+            "archive": false,
+            "tracked_shards": [],
+            "rpc": {
+                "addr": "0.0.0.0:3030",
+                "limits_config": { "json_payload_max_size": 10485760 },
+            },
+            "consensus": { "min_block_production_delay": { "secs": 0, "nanos": 600000000 } },
+        })
+    }
+
+    #[test]
+    fn known_and_optional_keys_pass() {
+        let additional_config = json!({
+            "archive": true,
+            // Only the keys of sections are checked, values like this list are left to nearcore.
+            "tracked_shards": [{ "anything": 0 }],
+            "rpc": {
+                "limits_config": { "json_payload_max_size": 1 },
+                "enable_debug_rpc": true,
+            },
+            "consensus": { "min_block_production_delay": { "secs": 1, "nanos": 0 } },
+            "save_trie_changes": true,
+            "state_sync": { "dump": { "location": "/tmp" } },
+        });
+        assert_eq!(
+            unknown_keys(&known(), &additional_config),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn unknown_keys_get_their_path_and_closest_match() {
+        let additional_config = json!({
+            "archival": true,
+            "rpc": { "limit_config": {}, "debug": true },
+            "something_else_entirely": 1,
+        });
+        assert_eq!(
+            unknown_keys(&known(), &additional_config),
+            [
+                "unknown config key `archival`, did you mean `archive`?",
+                "unknown config key `rpc.debug`",
+                "unknown config key `rpc.limit_config`, did you mean `limits_config`?",
+                "unknown config key `something_else_entirely`",
+            ]
+        );
+    }
+
+    #[test]
+    fn modes_decide_what_happens_to_unknown_keys() {
+        let additional_config = json!({ "archival": true });
+        assert!(check(ConfigKeyCheck::Off, &known(), &additional_config).is_ok());
+        assert!(check(ConfigKeyCheck::Warn, &known(), &additional_config).is_ok());
+        let err = check(ConfigKeyCheck::Deny, &known(), &additional_config).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid additional_config:\n  - unknown config key `archival`, did you mean `archive`?"
+        );
+    }
+
+    #[test]
+    fn edit_distance_counts_single_character_edits() {
+        assert_eq!(edit_distance("archive", "archive"), 0);
+        assert_eq!(edit_distance("archival", "archive"), 2);
+        assert_eq!(edit_distance("limit_config", "limits_config"), 1);
+        assert_eq!(edit_distance("", "rpc"), 3);
+    }
+}
@@ -0,0 +1,213 @@
+//! Genesis account keys in the credentials layout of near-cli.
+//!
+//! near-cli and most NEAR tooling look up the key of an account in
+//! `~/.near-credentials/<network>/<account_id>.json`. Exporting the sandbox's genesis accounts
+//! in that layout lets those tools, as well as tests written in other languages, sign with the
+//! sandbox accounts without any extra setup.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use super::config::{GenesisAccount, DEFAULT_GENESIS_ACCOUNT_BALANCE};
+
+/// Network name the sandbox accounts are exported under unless configured otherwise.
+pub const DEFAULT_CREDENTIALS_NETWORK: &str = "sandbox";
+
+#[derive(Serialize, Deserialize)]
+struct CredentialsFile {
+    account_id: String,
+    public_key: String,
+    // Older tooling calls the key `secret_key`.
+    #[serde(alias = "secret_key")]
+    private_key: String,
+}
+
+/// `~/.near-credentials`, the directory near-cli keeps its keys in.
+pub fn default_credentials_dir() -> anyhow::Result<PathBuf> {
+    let home = home::home_dir().ok_or_else(|| anyhow::anyhow!("could not find home directory"))?;
+    Ok(home.join(".near-credentials"))
+}
+
+/// Write the keys of `accounts` into `<dir>/<network>/<account_id>.json`, overwriting existing
+/// files of the same accounts. Returns the paths of the written files.
+pub fn export_credentials(
+    dir: impl AsRef<Path>,
+    network: &str,
+    accounts: &[GenesisAccount],
+) -> anyhow::Result<Vec<PathBuf>> {
+    let network_dir = dir.as_ref().join(network);
+    fs::create_dir_all(&network_dir).with_context(|| {
+        format!(
+            "could not create credentials directory {}",
+            network_dir.display()
+        )
+    })?;
+
+    let mut paths = Vec::with_capacity(accounts.len());
+    for account in accounts {
+        let path = network_dir.join(format!("{}.json", account.account_id));
+        let content = serde_json::to_string(&CredentialsFile {
+            account_id: account.account_id.clone(),
+            public_key: account.public_key.clone(),
+            private_key: account.private_key.clone(),
+        })?;
+
+        let mut file = create_private(&path)
+            .with_context(|| format!("could not write credentials {}", path.display()))?;
+        file.write_all(content.as_bytes())?;
+        file.flush()?;
+        paths.push(path);
+    }
+
+    Ok(paths)
+}
+
+/// Load the accounts of `<dir>/<network>`. The credential files carry no balance, so the
+/// accounts get [`DEFAULT_GENESIS_ACCOUNT_BALANCE`] and can be adjusted before being added
+/// to a [`SandboxConfig`](super::SandboxConfig).
+pub fn load_credentials(
+    dir: impl AsRef<Path>,
+    network: &str,
+) -> anyhow::Result<Vec<GenesisAccount>> {
+    let network_dir = dir.as_ref().join(network);
+    let entries = fs::read_dir(&network_dir).with_context(|| {
+        format!(
+            "could not read credentials directory {}",
+            network_dir.display()
+        )
+    })?;
+
+    let mut accounts = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        // Newer tooling keeps per-key files in a directory per account, which we skip.
+        if !path.is_file() || path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+            continue;
+        }
+
+        let content = fs::read_to_string(&path)?;
+        let credentials: CredentialsFile = serde_json::from_str(&content)
+            .with_context(|| format!("invalid credentials file {}", path.display()))?;
+        accounts.push(GenesisAccount {
+            account_id: credentials.account_id,
+            public_key: credentials.public_key,
+            private_key: credentials.private_key,
+            balance: DEFAULT_GENESIS_ACCOUNT_BALANCE,
+            ..GenesisAccount::default()
+        });
+    }
+    accounts.sort_by(|a, b| a.account_id.cmp(&b.account_id));
+
+    Ok(accounts)
+}
+
+/// Create `path` readable by the current user only, as it holds a private key.
+#[cfg(unix)]
+fn create_private(path: &Path) -> std::io::Result<fs::File> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    // The mode only applies to new files.
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    Ok(file)
+}
+
+#[cfg(not(unix))]
+fn create_private(path: &Path) -> std::io::Result<fs::File> {
+    fs::File::create(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(account_id: &str) -> GenesisAccount {
+        let default = GenesisAccount::default();
+        GenesisAccount::new(account_id, default.public_key, default.private_key, 1)
+    }
+
+    #[test]
+    fn exported_credentials_load_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let accounts = [account("bob.sandbox"), account("alice.sandbox")];
+
+        let paths = export_credentials(dir.path(), "localnet", &accounts).unwrap();
+        assert_eq!(
+            paths,
+            [
+                dir.path().join("localnet/bob.sandbox.json"),
+                dir.path().join("localnet/alice.sandbox.json"),
+            ]
+        );
+        let content: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&paths[0]).unwrap()).unwrap();
+        assert_eq!(
+            content,
+            serde_json::json!({
+                "account_id": "bob.sandbox",
+                "public_key": accounts[0].public_key,
+                "private_key": accounts[0].private_key,
+            })
+        );
+
+        let loaded = load_credentials(dir.path(), "localnet").unwrap();
+        let ids: Vec<_> = loaded.iter().map(|a| a.account_id.as_str()).collect();
+        assert_eq!(ids, ["alice.sandbox", "bob.sandbox"]);
+        assert_eq!(loaded[0].public_key, accounts[1].public_key);
+        assert_eq!(loaded[0].private_key, accounts[1].private_key);
+        // The balance isn't part of the credentials.
+        assert_eq!(loaded[0].balance, DEFAULT_GENESIS_ACCOUNT_BALANCE);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exported_credentials_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sandbox/alice.sandbox.json");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        // Overwritten files lose their previous permissions too.
+        fs::write(&path, "{}").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let paths = export_credentials(dir.path(), "sandbox", &[account("alice.sandbox")]).unwrap();
+        assert_eq!(paths.len(), 1);
+        assert_eq!(paths[0], path);
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn loading_accepts_secret_key_and_skips_key_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let network_dir = dir.path().join("testnet");
+        fs::create_dir_all(network_dir.join("bob.testnet")).unwrap();
+        fs::write(
+            network_dir.join("bob.testnet/ed25519_abc.json"),
+            r#"{"account_id":"bob.testnet","public_key":"ed25519:b","private_key":"ed25519:c"}"#,
+        )
+        .unwrap();
+        fs::write(
+            network_dir.join("alice.testnet.json"),
+            r#"{"account_id":"alice.testnet","public_key":"ed25519:a","secret_key":"ed25519:s"}"#,
+        )
+        .unwrap();
+        fs::write(network_dir.join("notes.txt"), "not a key").unwrap();
+
+        let loaded = load_credentials(dir.path(), "testnet").unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].account_id, "alice.testnet");
+        assert_eq!(loaded[0].public_key, "ed25519:a");
+        assert_eq!(loaded[0].private_key, "ed25519:s");
+    }
+}
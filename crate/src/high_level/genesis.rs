@@ -0,0 +1,462 @@
+//! Validation of the genesis the sandbox gets started with.
+//!
+//! The node refuses to start on an inconsistent genesis, but only tells about the first problem
+//! it finds, in its own logs. Checking the accounts up front lets us report every offending
+//! entry at once, together with the account it belongs to.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use serde_json::Value;
+
+use super::config::{GenesisAccessKey, GenesisAccount};
+use super::signer::Signer;
+
+const MIN_ACCOUNT_ID_LEN: usize = 2;
+const MAX_ACCOUNT_ID_LEN: usize = 64;
+
+/// Key types nearcore supports, with the length of their public keys in bytes.
+const KEY_TYPES: &[(&str, usize)] = &[("ed25519", 32), ("secp256k1", 64)];
+
+/// Split `key` into its type and base58 data. Like for nearcore, keys without a type are
+/// ed25519 keys.
+pub(crate) fn split_key(key: &str) -> (&str, &str) {
+    key.split_once(':').unwrap_or(("ed25519", key))
+}
+
+/// Length in bytes of the public keys of `key_type`, if nearcore supports it.
+pub(crate) fn public_key_len(key_type: &str) -> Option<usize> {
+    KEY_TYPES
+        .iter()
+        .find(|(name, _)| *name == key_type)
+        .map(|(_, len)| *len)
+}
+
+/// Check `account_id` against the NEAR account ID rules: 2 to 64 characters, made of lowercase
+/// alphanumeric parts separated by single `.`, `-` or `_`.
+pub(crate) fn validate_account_id(account_id: &str) -> Result<(), String> {
+    if !(MIN_ACCOUNT_ID_LEN..=MAX_ACCOUNT_ID_LEN).contains(&account_id.len()) {
+        return Err(format!(
+            "must be between {MIN_ACCOUNT_ID_LEN} and {MAX_ACCOUNT_ID_LEN} characters long"
+        ));
+    }
+
+    let mut last_was_separator = true;
+    for c in account_id.chars() {
+        match c {
+            'a'..='z' | '0'..='9' => last_was_separator = false,
+            '.' | '-' | '_' if !last_was_separator => last_was_separator = true,
+            '.' | '-' | '_' => return Err(format!("has a misplaced separator `{c}`")),
+            _ => return Err(format!("contains invalid character `{c}`")),
+        }
+    }
+    if last_was_separator {
+        return Err("ends with a separator".to_string());
+    }
+
+    Ok(())
+}
+
+/// Check a key pair of `account_id`, describing problems with the `label` of the key. Only
+/// ed25519 private keys are checked to belong to the public key, as those are the ones
+/// [`Signer`] supports, other types are left to the node.
+fn validate_key(
+    problems: &mut Vec<String>,
+    account_id: &str,
+    label: &str,
+    public_key: &str,
+    private_key: Option<&str>,
+) {
+    let (key_type, data) = split_key(public_key);
+    let Some(len) = public_key_len(key_type) else {
+        problems.push(format!(
+            "account `{account_id}`: {label} public key `{public_key}` has unsupported key type \
+             `{key_type}`"
+        ));
+        return;
+    };
+    match bs58::decode(data).into_vec() {
+        Ok(bytes) if bytes.len() == len => {}
+        Ok(bytes) => {
+            problems.push(format!(
+                "account `{account_id}`: {label} public key `{public_key}` has {} bytes, \
+                 expected {len}",
+                bytes.len()
+            ));
+            return;
+        }
+        Err(err) => {
+            problems.push(format!(
+                "account `{account_id}`: {label} public key `{public_key}` is not valid base58: \
+                 {err}"
+            ));
+            return;
+        }
+    }
+
+    let Some(private_key) = private_key else {
+        return;
+    };
+    let (private_key_type, private_data) = split_key(private_key);
+    if key_type != "ed25519" || private_key_type != "ed25519" {
+        return;
+    }
+    match Signer::from_secret_key(account_id, &format!("ed25519:{private_data}")) {
+        Ok(signer) if signer.public_key().to_string() != format!("ed25519:{data}") => problems
+            .push(format!(
+                "account `{account_id}`: {label} private key doesn't belong to `{public_key}`"
+            )),
+        Ok(_) => {}
+        Err(err) => problems.push(format!(
+            "account `{account_id}`: {label} private key is invalid: {err:#}"
+        )),
+    }
+}
+
+/// Collect every problem with the genesis `accounts`: invalid or duplicate account IDs, and
+/// malformed, mismatching or duplicate keys.
+pub(crate) fn validate_accounts(accounts: &[GenesisAccount]) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut account_ids = HashSet::new();
+
+    for account in accounts {
+        let account_id = account.account_id.as_str();
+        if let Err(err) = validate_account_id(account_id) {
+            problems.push(format!("account `{account_id}`: invalid account ID, {err}"));
+        }
+        if !account_ids.insert(account_id) {
+            problems.push(format!("account `{account_id}`: defined more than once"));
+        }
+
+        validate_key(
+            &mut problems,
+            account_id,
+            "primary",
+            &account.public_key,
+            Some(&account.private_key),
+        );
+
+        let mut public_keys = HashSet::new();
+        public_keys.insert(account.public_key.as_str());
+        for GenesisAccessKey {
+            public_key,
+            private_key,
+            access_key: _,
+        } in &account.additional_keys
+        {
+            validate_key(
+                &mut problems,
+                account_id,
+                "additional",
+                public_key,
+                private_key.as_deref(),
+            );
+            if !public_keys.insert(public_key) {
+                problems.push(format!(
+                    "account `{account_id}`: key `{public_key}` added more than once"
+                ));
+            }
+        }
+    }
+
+    problems
+}
+
+/// Check the final genesis `records` for accounts defined more than once, e.g. a configured
+/// account clashing with one `init` created, and sum up the `amount` and `locked` balances of
+/// all `Account` records, which is what the node expects as `total_supply`.
+pub(crate) fn check_records(records: &[Value], problems: &mut Vec<String>) -> u128 {
+    let mut total_supply: u128 = 0;
+    let mut account_ids = HashSet::new();
+
+    for record in records {
+        let Some(account) = record.get("Account") else {
+            continue;
+        };
+        let account_id = account["account_id"].as_str().unwrap_or("<missing>");
+        if !account_ids.insert(account_id) {
+            problems.push(format!(
+                "account `{account_id}`: defined more than once in the genesis records"
+            ));
+        }
+
+        for field in ["amount", "locked"] {
+            let balance = account["account"][field]
+                .as_str()
+                .and_then(|balance| balance.parse::<u128>().ok());
+            match balance {
+                Some(balance) => match total_supply.checked_add(balance) {
+                    Some(sum) => total_supply = sum,
+                    None => {
+                        problems.push("total supply overflows u128".to_string());
+                        return total_supply;
+                    }
+                },
+                None => problems.push(format!(
+                    "record of account `{account_id}`: `{field}` is not a decimal string"
+                )),
+            }
+        }
+    }
+
+    total_supply
+}
+
+/// Check that the accounts locking a balance in `records` are exactly the genesis `validators`,
+/// each staking its locked balance. The node refuses to start otherwise.
+pub(crate) fn validate_stakes(records: &[Value], validators: &Value, problems: &mut Vec<String>) {
+    let mut locked = HashMap::new();
+    for record in records {
+        let Some(account) = record.get("Account") else {
+            continue;
+        };
+        let balance = account["account"]["locked"]
+            .as_str()
+            .and_then(|balance| balance.parse::<u128>().ok());
+        // Malformed balances are already reported by `check_records`.
+        if let (Some(account_id), Some(balance)) = (account["account_id"].as_str(), balance) {
+            if balance > 0 {
+                locked.insert(account_id, balance);
+            }
+        }
+    }
+
+    let Some(validators) = validators.as_array() else {
+        problems.push("`validators` is not an array".to_string());
+        return;
+    };
+    let mut staked = HashSet::new();
+    for validator in validators {
+        let account_id = validator["account_id"].as_str().unwrap_or("<missing>");
+        let Some(amount) = validator["amount"]
+            .as_str()
+            .and_then(|amount| amount.parse::<u128>().ok())
+        else {
+            problems.push(format!(
+                "validator `{account_id}`: `amount` is not a decimal string"
+            ));
+            continue;
+        };
+        staked.insert(account_id);
+        match locked.get(account_id) {
+            Some(&balance) if balance == amount => {}
+            Some(balance) => problems.push(format!(
+                "validator `{account_id}`: stakes {amount} but its account locks {balance}"
+            )),
+            None => problems.push(format!(
+                "validator `{account_id}`: stakes {amount} but its account locks nothing"
+            )),
+        }
+    }
+
+    let mut unstaked: Vec<_> = locked
+        .into_iter()
+        .filter(|(account_id, _)| !staked.contains(account_id))
+        .collect();
+    unstaked.sort();
+    for (account_id, balance) in unstaked {
+        problems.push(format!(
+            "account `{account_id}`: locks {balance} but is not among the genesis `validators`"
+        ));
+    }
+}
+
+/// Turn the collected `problems` into a single error listing all of them.
+pub(crate) fn check(problems: Vec<String>) -> anyhow::Result<()> {
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    let mut message = format!(
+        "invalid sandbox genesis, found {} problem(s):",
+        problems.len()
+    );
+    for problem in problems {
+        let _ = write!(message, "\n  - {problem}");
+    }
+    Err(anyhow::anyhow!(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn account(account_id: &str, amount: &str, locked: &str) -> Value {
+        json!({
+            "Account": {
+                "account_id": account_id,
+                "account": { "amount": amount, "locked": locked },
+            }
+        })
+    }
+
+    fn validator(account_id: &str, amount: &str) -> Value {
+        json!({ "account_id": account_id, "public_key": "ed25519:x", "amount": amount })
+    }
+
+    #[test]
+    fn accounts_are_validated_all_at_once() {
+        let default = GenesisAccount::default();
+        let mut invalid =
+            GenesisAccount::new("Alice", "ed25519:zz0", default.private_key.clone(), 0);
+        invalid.additional_keys = vec![
+            GenesisAccessKey::full_access(default.public_key.clone(), None),
+            GenesisAccessKey::full_access(default.public_key.clone(), None),
+        ];
+
+        let problems = validate_accounts(&[default.clone(), default, invalid]);
+        assert_eq!(problems.len(), 4, "{:?}", problems);
+        assert_eq!(problems[0], "account `sandbox`: defined more than once");
+        assert_eq!(
+            problems[1],
+            "account `Alice`: invalid account ID, contains invalid character `A`"
+        );
+        assert!(
+            problems[2].starts_with("account `Alice`: primary public key `ed25519:zz0`"),
+            "{}",
+            problems[2]
+        );
+        assert!(
+            problems[3].ends_with("added more than once"),
+            "{}",
+            problems[3]
+        );
+
+        assert!(validate_accounts(&[GenesisAccount::default()]).is_empty());
+    }
+
+    #[test]
+    fn keys_of_all_supported_types_are_accepted() {
+        let default = GenesisAccount::default();
+        let secp256k1 = format!("secp256k1:{}", bs58::encode([7; 64]).into_string());
+        let mut problems = Vec::new();
+        validate_key(
+            &mut problems,
+            "a.near",
+            "primary",
+            &secp256k1,
+            Some("secp256k1:whatever"),
+        );
+        validate_key(
+            &mut problems,
+            "a.near",
+            "primary",
+            default.public_key.trim_start_matches("ed25519:"),
+            Some(&default.private_key),
+        );
+        assert!(problems.is_empty(), "{:?}", problems);
+
+        let short = format!("secp256k1:{}", bs58::encode([7; 33]).into_string());
+        validate_key(&mut problems, "a.near", "primary", &short, None);
+        validate_key(&mut problems, "a.near", "primary", "rsa:abc", None);
+        assert_eq!(
+            problems,
+            [
+                format!(
+                    "account `a.near`: primary public key `{}` has 33 bytes, expected 64",
+                    short
+                ),
+                "account `a.near`: primary public key `rsa:abc` has unsupported key type `rsa`"
+                    .to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn account_ids_follow_the_near_rules() {
+        for valid in ["near", "test.near", "a-b_c.d0", "00"] {
+            assert_eq!(validate_account_id(valid), Ok(()), "{}", valid);
+        }
+        for invalid in [
+            "a",
+            "-near",
+            "near.",
+            "a..b",
+            "a_-b",
+            "Near",
+            "a b",
+            &"a".repeat(65),
+        ] {
+            assert!(validate_account_id(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn total_supply_sums_amount_and_locked() {
+        let records = [
+            account("test.near", "10", "5"),
+            json!({ "AccessKey": { "account_id": "test.near" } }),
+            account("alice.near", &u64::MAX.to_string(), "0"),
+        ];
+        let mut problems = Vec::new();
+        assert_eq!(
+            check_records(&records, &mut problems),
+            15 + u128::from(u64::MAX)
+        );
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn records_are_checked_for_duplicates_and_malformed_balances() {
+        let records = [
+            account("test.near", "10", "5"),
+            json!({ "Account": { "account_id": "bob.near", "account": { "amount": 7, "locked": "0" } } }),
+            account("test.near", "1", "0"),
+        ];
+        let mut problems = Vec::new();
+        check_records(&records, &mut problems);
+        assert_eq!(
+            problems,
+            [
+                "record of account `bob.near`: `amount` is not a decimal string",
+                "account `test.near`: defined more than once in the genesis records",
+            ]
+        );
+
+        let max = u128::MAX.to_string();
+        let records = [account("a.near", &max, "0"), account("b.near", "1", "0")];
+        let mut problems = Vec::new();
+        check_records(&records, &mut problems);
+        assert_eq!(problems, ["total supply overflows u128"]);
+    }
+
+    #[test]
+    fn stakes_must_match_the_validators() {
+        let records = [
+            account("test.near", "10", "5"),
+            account("alice.near", "10", "3"),
+            account("bob.near", "10", "0"),
+        ];
+        let mut problems = Vec::new();
+        validate_stakes(
+            &records,
+            &json!([validator("test.near", "5")]),
+            &mut problems,
+        );
+        assert_eq!(
+            problems,
+            ["account `alice.near`: locks 3 but is not among the genesis `validators`"]
+        );
+
+        let validators = json!([
+            validator("test.near", "5"),
+            validator("alice.near", "4"),
+            validator("bob.near", "1"),
+        ]);
+        let mut problems = Vec::new();
+        validate_stakes(&records, &validators, &mut problems);
+        assert_eq!(
+            problems,
+            [
+                "validator `alice.near`: stakes 4 but its account locks 3",
+                "validator `bob.near`: stakes 1 but its account locks nothing",
+            ]
+        );
+
+        let validators = json!([validator("test.near", "5"), validator("alice.near", "3")]);
+        let mut problems = Vec::new();
+        validate_stakes(&records, &validators, &mut problems);
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+}
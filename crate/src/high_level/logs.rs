@@ -0,0 +1,225 @@
+//! Forwarding of the sandbox node's log output into `tracing`.
+//!
+//! nearcore writes its logs in the default `tracing-subscriber` format:
+//! `2024-01-01T00:00:00.000000Z  INFO span{field=1}: near_chain: message key=value`.
+//! Every line gets parsed back into its level, nearcore target and message and re-emitted as a
+//! `tracing` event with the `sandbox::node` target. The original target is kept in the
+//! `near_target` field, next to the `pid` and `port` of the sandbox the line came from, so
+//! subscribers can tell multiple sandboxes apart and filter per test.
+
+use std::io::BufRead;
+
+use tracing::Level;
+
+/// Target of the events emitted for the node's log lines.
+pub const NODE_LOG_TARGET: &str = "sandbox::node";
+
+#[derive(Debug)]
+struct LogLine<'a> {
+    level: Level,
+    target: &'a str,
+    message: &'a str,
+}
+
+/// Remove ANSI color codes in case the node was forced to colorize its output.
+fn strip_ansi(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            // Skip the control sequence up to and including its final letter.
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn parse_level(level: &str) -> Option<Level> {
+    match level {
+        "TRACE" => Some(Level::TRACE),
+        "DEBUG" => Some(Level::DEBUG),
+        "INFO" => Some(Level::INFO),
+        "WARN" => Some(Level::WARN),
+        "ERROR" => Some(Level::ERROR),
+        _ => None,
+    }
+}
+
+fn is_target(segment: &str) -> bool {
+    !segment.is_empty()
+        && segment
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+fn parse_line(line: &str) -> Option<LogLine<'_>> {
+    let mut parts = line.trim_start().splitn(2, char::is_whitespace);
+    let _timestamp = parts.next()?;
+    let rest = parts.next()?.trim_start();
+    let (level, mut rest) = rest.split_once(char::is_whitespace)?;
+    let level = parse_level(level)?;
+
+    // Skip over the spans, which may contain `: ` within their fields, until the target.
+    loop {
+        let mut depth = 0;
+        let mut end = None;
+        for (i, c) in rest.char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                ':' if depth == 0 && rest[i + 1..].starts_with(' ') => {
+                    end = Some(i);
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        let Some(end) = end else {
+            return Some(LogLine {
+                level,
+                target: "",
+                message: rest.trim(),
+            });
+        };
+        let (segment, tail) = (&rest[..end], rest[end + 1..].trim_start());
+        if is_target(segment) {
+            return Some(LogLine {
+                level,
+                target: segment,
+                message: tail,
+            });
+        }
+        rest = tail;
+    }
+}
+
+/// Re-emit a single log line of the sandbox with the given `pid` and `port` as tracing event.
+pub(crate) fn emit(line: &str, pid: Option<u32>, port: u16) {
+    let line = strip_ansi(line);
+    if line.trim().is_empty() {
+        return;
+    }
+
+    macro_rules! forward {
+        ($level:expr, $target:expr, $message:expr) => {
+            tracing::event!(
+                target: NODE_LOG_TARGET,
+                $level,
+                pid,
+                port,
+                near_target = $target,
+                "{}",
+                $message
+            )
+        };
+    }
+
+    match parse_line(&line) {
+        Some(LogLine {
+            level,
+            target,
+            message,
+        }) => match level {
+            Level::ERROR => forward!(Level::ERROR, target, message),
+            Level::WARN => forward!(Level::WARN, target, message),
+            Level::INFO => forward!(Level::INFO, target, message),
+            Level::DEBUG => forward!(Level::DEBUG, target, message),
+            Level::TRACE => forward!(Level::TRACE, target, message),
+        },
+        // Lines not written by the logger, e.g. panic messages.
+        None => forward!(Level::INFO, "", line.trim_end()),
+    }
+}
+
+/// Forward every line of `reader` until it is closed.
+pub(crate) fn forward(reader: impl BufRead, pid: Option<u32>, port: u16) {
+    for line in reader.lines() {
+        match line {
+            Ok(line) => emit(&line, pid, port),
+            Err(_) => break,
+        }
+    }
+}
+
+/// Forward the lines of an async `reader` until it is closed.
+pub(crate) async fn forward_async(
+    reader: impl tokio::io::AsyncRead + Unpin,
+    pid: Option<u32>,
+    port: u16,
+) {
+    use tokio::io::AsyncBufReadExt;
+
+    let mut lines = tokio::io::BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        emit(&line, pid, port);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Option<(Level, &str, &str)> {
+        parse_line(line).map(|line| (line.level, line.target, line.message))
+    }
+
+    #[test]
+    fn parses_level_target_and_message() {
+        assert_eq!(
+            parse("2024-01-01T00:00:00.000000Z  INFO near_chain: Block produced height=10"),
+            Some((Level::INFO, "near_chain", "Block produced height=10"))
+        );
+        assert_eq!(
+            parse(
+                "2024-01-01T00:00:00.000000Z ERROR near_client::sync: state sync failed: timeout"
+            ),
+            Some((
+                Level::ERROR,
+                "near_client::sync",
+                "state sync failed: timeout"
+            ))
+        );
+    }
+
+    #[test]
+    fn skips_spans_with_separators_in_their_fields() {
+        assert_eq!(
+            parse(
+                "2024-01-01T00:00:00.000000Z DEBUG produce_block{height=5 note=\"a: b\"}:apply: \
+                 runtime: applying chunk"
+            ),
+            Some((Level::DEBUG, "runtime", "applying chunk"))
+        );
+    }
+
+    #[test]
+    fn lines_without_target_keep_the_message() {
+        assert_eq!(
+            parse("2024-01-01T00:00:00.000000Z  WARN something odd happened"),
+            Some((Level::WARN, "", "something odd happened"))
+        );
+    }
+
+    #[test]
+    fn rejects_lines_not_written_by_the_logger() {
+        assert_eq!(parse("thread 'main' panicked at src/main.rs:1:1:"), None);
+        assert_eq!(parse("init ok"), None);
+        assert_eq!(parse(""), None);
+    }
+
+    #[test]
+    fn strips_colors() {
+        let line = strip_ansi(
+            "\u{1b}[2m2024-01-01T00:00:00.000000Z\u{1b}[0m \u{1b}[32m INFO\u{1b}[0m \
+             \u{1b}[2mneard\u{1b}[0m\u{1b}[2m:\u{1b}[0m starting",
+        );
+        assert_eq!(parse(&line), Some((Level::INFO, "neard", "starting")));
+    }
+}
@@ -0,0 +1,236 @@
+//! Resource usage of a running sandbox node.
+//!
+//! [`Metrics`] holds the node's Prometheus metrics as scraped from the `/metrics` endpoint
+//! of its RPC server, [`ProcessStats`] the resources the node process uses according to the OS.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// A single sample of a Prometheus metric.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricSample {
+    pub name: String,
+    pub labels: BTreeMap<String, String>,
+    pub value: f64,
+}
+
+/// Prometheus metrics exposed by the sandbox node.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metrics {
+    pub samples: Vec<MetricSample>,
+}
+
+impl Metrics {
+    /// Parse metrics in the Prometheus text exposition format.
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let samples = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                parse_sample(line).ok_or_else(|| anyhow::anyhow!("malformed metric line: {line}"))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self { samples })
+    }
+
+    /// All the samples of the metric `name`.
+    pub fn samples<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a MetricSample> + 'a {
+        self.samples
+            .iter()
+            .filter(move |sample| sample.name == name)
+    }
+
+    /// Value of the metric `name`, taking the first sample if it has several label sets.
+    pub fn get(&self, name: &str) -> Option<f64> {
+        self.samples(name).next().map(|sample| sample.value)
+    }
+
+    /// Value of the metric `name` with exactly the given labels.
+    pub fn get_with_labels(&self, name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+        self.samples(name)
+            .find(|sample| {
+                sample.labels.len() == labels.len()
+                    && labels.iter().all(|(key, value)| {
+                        sample.labels.get(*key).map(String::as_str) == Some(*value)
+                    })
+            })
+            .map(|sample| sample.value)
+    }
+}
+
+fn parse_sample(line: &str) -> Option<MetricSample> {
+    let name_end = line.find(|c: char| c == '{' || c.is_whitespace())?;
+    let name = line[..name_end].to_string();
+    let mut rest = &line[name_end..];
+
+    let mut labels = BTreeMap::new();
+    if let Some(stripped) = rest.strip_prefix('{') {
+        rest = stripped;
+        loop {
+            rest = rest.trim_start_matches([',', ' ']);
+            if let Some(stripped) = rest.strip_prefix('}') {
+                rest = stripped;
+                break;
+            }
+
+            let (key, tail) = rest.split_once('=')?;
+            let tail = tail.strip_prefix('"')?;
+
+            let mut value = String::new();
+            let mut chars = tail.char_indices();
+            let end = loop {
+                match chars.next()? {
+                    (i, '"') => break i,
+                    (_, '\\') => match chars.next()?.1 {
+                        'n' => value.push('\n'),
+                        c => value.push(c),
+                    },
+                    (_, c) => value.push(c),
+                }
+            };
+
+            labels.insert(key.trim().to_string(), value);
+            rest = &tail[end + 1..];
+        }
+    }
+
+    // The value may be followed by an optional timestamp.
+    let value = rest.split_whitespace().next()?;
+    let value = match value {
+        "+Inf" => f64::INFINITY,
+        "-Inf" => f64::NEG_INFINITY,
+        value => value.parse().ok()?,
+    };
+
+    Some(MetricSample {
+        name,
+        labels,
+        value,
+    })
+}
+
+/// Resources used by the sandbox node process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessStats {
+    /// Resident set size in bytes.
+    pub rss_bytes: u64,
+    /// CPU time spent in user mode.
+    pub user_time: Duration,
+    /// CPU time spent in kernel mode.
+    pub system_time: Duration,
+    /// Number of open file descriptors.
+    pub open_files: usize,
+}
+
+impl ProcessStats {
+    /// Total CPU time of the process.
+    pub fn cpu_time(&self) -> Duration {
+        self.user_time + self.system_time
+    }
+
+    /// Read the stats of process `pid` from `/proc`.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn of_process(pid: u32) -> anyhow::Result<Self> {
+        use anyhow::Context;
+
+        // The times in /proc/{pid}/stat are in USER_HZ, which is 100 on all Linux platforms.
+        const TICKS_PER_SEC: u64 = 100;
+
+        let proc = std::path::PathBuf::from(format!("/proc/{pid}"));
+
+        let stat = std::fs::read_to_string(proc.join("stat"))
+            .with_context(|| format!("failed to read stats of sandbox pid={pid}"))?;
+        // The command name may contain spaces, so start counting fields after it.
+        let fields: Vec<&str> = stat
+            .rsplit_once(')')
+            .map(|(_, fields)| fields.split_whitespace().collect())
+            .unwrap_or_default();
+        // utime and stime are the 14th and 15th field, the 3rd and later are after the name.
+        let ticks = |index: usize| -> anyhow::Result<Duration> {
+            let ticks: u64 = fields
+                .get(index - 3)
+                .ok_or_else(|| anyhow::anyhow!("malformed /proc/{pid}/stat"))?
+                .parse()?;
+            Ok(Duration::from_millis(ticks * 1000 / TICKS_PER_SEC))
+        };
+
+        let status = std::fs::read_to_string(proc.join("status"))?;
+        let rss_kb: u64 = status
+            .lines()
+            .find_map(|line| line.strip_prefix("VmRSS:"))
+            .and_then(|value| value.split_whitespace().next())
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0);
+
+        let open_files = std::fs::read_dir(proc.join("fd"))?.count();
+
+        Ok(Self {
+            rss_bytes: rss_kb * 1024,
+            user_time: ticks(14)?,
+            system_time: ticks(15)?,
+            open_files,
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn of_process(_pid: u32) -> anyhow::Result<Self> {
+        anyhow::bail!("process stats of the sandbox are only available on Linux")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = r#"
+# HELP near_block_height_head Height of the current head of the blockchain
+# TYPE near_block_height_head gauge
+near_block_height_head 42
+near_rpc_processing_time_count{method="block"} 7 1700000000000
+near_rpc_processing_time_count{method="query",shard=""} 3
+near_label_escapes{path="a\"b\\c\nd"} 1.5
+near_rpc_processing_time_bucket{method="block",le="+Inf"} +Inf
+"#;
+
+    #[test]
+    fn parses_samples_with_labels_and_timestamps() {
+        let metrics = Metrics::parse(TEXT).unwrap();
+
+        assert_eq!(metrics.samples.len(), 5);
+        assert_eq!(metrics.get("near_block_height_head"), Some(42.0));
+        assert_eq!(
+            metrics.get_with_labels("near_rpc_processing_time_count", &[("method", "block")]),
+            Some(7.0)
+        );
+        assert_eq!(
+            metrics.get_with_labels(
+                "near_rpc_processing_time_count",
+                &[("method", "query"), ("shard", "")]
+            ),
+            Some(3.0)
+        );
+        // Labels have to match exactly.
+        assert_eq!(
+            metrics.get_with_labels("near_rpc_processing_time_count", &[("method", "query")]),
+            None
+        );
+        assert_eq!(
+            metrics.get_with_labels("near_label_escapes", &[("path", "a\"b\\c\nd")]),
+            Some(1.5)
+        );
+        assert_eq!(
+            metrics.get("near_rpc_processing_time_bucket"),
+            Some(f64::INFINITY)
+        );
+        assert_eq!(metrics.get("near_missing"), None);
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert!(Metrics::parse("near_block_height_head").is_err());
+        assert!(Metrics::parse("near_block_height_head{method=\"block} 1").is_err());
+        assert!(Metrics::parse("near_block_height_head forty-two").is_err());
+    }
+}
@@ -0,0 +1,425 @@
+//! In-process stand-in for a sandbox node's JSON-RPC server.
+//!
+//! Unit tests that only need an RPC endpoint answering a few methods don't have to pay for
+//! installing and booting `neard`. [`MockSandbox`] serves JSON-RPC over plain HTTP from a
+//! background thread, so it works the same in async and blocking test suites.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tracing::info;
+
+use super::{acquire_port, rpc_url, PortLock, DEFAULT_BIND_ADDR};
+
+/// How long an idle keep-alive connection is kept open.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+type Handler = Arc<dyn Fn(&Value) -> Result<Value, Value> + Send + Sync>;
+
+/// A JSON-RPC request received by a [`MockSandbox`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockRequest {
+    pub id: Value,
+    pub method: String,
+    pub params: Value,
+}
+
+#[derive(Default)]
+struct MockState {
+    handlers: Mutex<HashMap<String, Handler>>,
+    requests: Mutex<Vec<MockRequest>>,
+}
+
+impl MockState {
+    fn handle(&self, request: &Value) -> Value {
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let Some(method) = request.get("method").and_then(Value::as_str) else {
+            return json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": -32600, "message": "Invalid Request" },
+            });
+        };
+        let params = request.get("params").cloned().unwrap_or(Value::Null);
+
+        self.requests.lock().unwrap().push(MockRequest {
+            id: id.clone(),
+            method: method.to_string(),
+            params: params.clone(),
+        });
+
+        self.respond(id, method, &params)
+    }
+
+    /// Answer a request without recording it.
+    fn respond(&self, id: Value, method: &str, params: &Value) -> Value {
+        let handler = self.handlers.lock().unwrap().get(method).cloned();
+        match handler.map(|handler| handler(params)) {
+            Some(Ok(result)) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Some(Err(error)) => json!({ "jsonrpc": "2.0", "id": id, "error": error }),
+            None => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": {
+                    "code": -32601,
+                    "message": "Method not found",
+                    "data": format!("no handler registered for `{method}`"),
+                },
+            }),
+        }
+    }
+}
+
+/// A mock sandbox that answers JSON-RPC requests with canned or programmable responses.
+///
+/// It exposes `rpc_addr` in the same shape as [`Sandbox`](crate::Sandbox), so it can be passed
+/// wherever a client is pointed at a sandbox. Out of the box it answers:
+/// - `status` (also on `GET /status`) with the status of an idle chain at height 0
+/// - `query` of `view_account`, `view_access_key` and `call_function` with an empty account,
+///   a full access key with nonce 0 and an empty result, and other request types with an error
+/// - `broadcast_tx_commit` with a successful outcome without receipts
+///
+/// Handlers registered with [`MockSandbox::register`] or [`MockSandbox::respond_with`] replace
+/// these answers, every other method fails with a `Method not found` error. All JSON-RPC
+/// requests are recorded for later assertions, plain `GET /status` readiness probes are not.
+pub struct MockSandbox {
+    pub rpc_addr: String,
+    pub rpc_port_lock: PortLock,
+    state: Arc<MockState>,
+    shutdown: Arc<AtomicBool>,
+    local_addr: SocketAddr,
+    server: Option<JoinHandle<()>>,
+}
+
+impl MockSandbox {
+    /// Start serving JSON-RPC on an unused localhost port.
+    pub fn start() -> anyhow::Result<Self> {
+        let mut rpc_port_lock = acquire_port(DEFAULT_BIND_ADDR, None, "RPC")?;
+        let listener = rpc_port_lock
+            .take_listener()
+            .expect("freshly acquired port is still bound");
+        let local_addr = listener.local_addr()?;
+
+        let state = Arc::new(MockState::default());
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let server = {
+            let state = state.clone();
+            let shutdown = shutdown.clone();
+            std::thread::Builder::new()
+                .name("mock-sandbox".to_string())
+                .spawn(move || serve(listener, state, shutdown))?
+        };
+
+        let mock = Self {
+            rpc_addr: rpc_url(local_addr),
+            rpc_port_lock,
+            state,
+            shutdown,
+            local_addr,
+            server: Some(server),
+        };
+        mock.respond_with("status", default_status());
+        mock.register("query", default_query);
+        mock.respond_with("broadcast_tx_commit", default_outcome());
+        info!(target: "sandbox", "Started up mock sandbox at {}", mock.rpc_addr);
+
+        Ok(mock)
+    }
+
+    /// Answer `method` with the outcome of `handler`, which gets the request params.
+    /// `Ok` values are sent as the `result`, `Err` values as the JSON-RPC `error` object.
+    /// Replaces any handler previously registered for `method`.
+    pub fn register<F>(&self, method: &str, handler: F)
+    where
+        F: Fn(&Value) -> Result<Value, Value> + Send + Sync + 'static,
+    {
+        self.state
+            .handlers
+            .lock()
+            .unwrap()
+            .insert(method.to_string(), Arc::new(handler));
+    }
+
+    /// Always answer `method` with the given `result`.
+    pub fn respond_with(&self, method: &str, result: Value) {
+        self.register(method, move |_| Ok(result.clone()));
+    }
+
+    /// All requests received so far, in the order they arrived.
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.state.requests.lock().unwrap().clone()
+    }
+
+    /// The requests received so far for `method`.
+    pub fn requests_for(&self, method: &str) -> Vec<MockRequest> {
+        self.requests()
+            .into_iter()
+            .filter(|request| request.method == method)
+            .collect()
+    }
+
+    /// Forget all the requests received so far.
+    pub fn clear_requests(&self) {
+        self.state.requests.lock().unwrap().clear();
+    }
+}
+
+impl Drop for MockSandbox {
+    fn drop(&mut self) {
+        info!(target: "sandbox", "Cleaning up mock sandbox at {}", self.rpc_addr);
+
+        self.shutdown.store(true, Ordering::SeqCst);
+        // Wake up the accept loop so it notices the shutdown.
+        let _ = TcpStream::connect(self.local_addr);
+        if let Some(server) = self.server.take() {
+            let _ = server.join();
+        }
+    }
+}
+
+/// Base58 of the all-zero hash, used for every block and code hash of the mock chain.
+const ZERO_HASH: &str = "11111111111111111111111111111111";
+
+fn default_status() -> Value {
+    json!({
+        "chain_id": "sandbox",
+        "version": { "version": "mock", "build": "mock" },
+        "protocol_version": 73,
+        "latest_protocol_version": 73,
+        "rpc_addr": null,
+        "validators": [],
+        "sync_info": {
+            "latest_block_hash": ZERO_HASH,
+            "latest_block_height": 0,
+            "latest_state_root": ZERO_HASH,
+            "latest_block_time": "1970-01-01T00:00:00.000000000Z",
+            "syncing": false,
+        },
+        "validator_account_id": null,
+    })
+}
+
+fn default_query(params: &Value) -> Result<Value, Value> {
+    let mut result = match params["request_type"].as_str() {
+        Some("view_account") => json!({
+            "amount": "0",
+            "locked": "0",
+            "code_hash": ZERO_HASH,
+            "storage_usage": 0,
+            "storage_paid_at": 0,
+        }),
+        Some("view_access_key") => json!({ "nonce": 0, "permission": "FullAccess" }),
+        Some("call_function") => json!({ "result": [], "logs": [] }),
+        request_type => {
+            return Err(json!({
+                "code": -32000,
+                "message": "Server error",
+                "data": format!("no canned answer for request type {request_type:?}"),
+            }))
+        }
+    };
+    result["block_height"] = json!(0);
+    result["block_hash"] = json!(ZERO_HASH);
+    Ok(result)
+}
+
+fn default_outcome() -> Value {
+    let success = json!({ "SuccessValue": "" });
+    json!({
+        "status": success.clone(),
+        "transaction_outcome": {
+            "id": ZERO_HASH,
+            "block_hash": ZERO_HASH,
+            "outcome": {
+                "status": success,
+                "logs": [],
+                "receipt_ids": [],
+                "gas_burnt": 0,
+                "tokens_burnt": "0",
+            },
+        },
+        "receipts_outcome": [],
+    })
+}
+
+fn serve(listener: TcpListener, state: Arc<MockState>, shutdown: Arc<AtomicBool>) {
+    for stream in listener.incoming() {
+        if shutdown.load(Ordering::SeqCst) {
+            break;
+        }
+        let Ok(stream) = stream else {
+            continue;
+        };
+
+        let state = state.clone();
+        std::thread::spawn(move || {
+            if let Err(err) = serve_connection(stream, &state) {
+                tracing::debug!(target: "sandbox", "mock sandbox connection closed: {err}");
+            }
+        });
+    }
+}
+
+/// Serve HTTP/1.1 requests on a single connection until the client closes it.
+fn serve_connection(stream: TcpStream, state: &MockState) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    loop {
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line)? == 0 {
+            return Ok(());
+        }
+        let mut parts = request_line.split_whitespace();
+        let http_method = parts.next().unwrap_or_default().to_string();
+        let path = parts.next().unwrap_or_default().to_string();
+
+        let mut content_length = 0;
+        let mut close = false;
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 {
+                return Ok(());
+            }
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                let value = value.trim();
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.parse().unwrap_or(0);
+                } else if name.eq_ignore_ascii_case("connection") {
+                    close = value.eq_ignore_ascii_case("close");
+                }
+            }
+        }
+
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+
+        let response = if http_method == "GET" && path.starts_with("/status") {
+            // Plain HTTP endpoint used for readiness checks, returns the bare status. Not recorded,
+            // so probes don't show up among the requests tests assert on.
+            state.respond(Value::Null, "status", &json!([]))["result"].clone()
+        } else {
+            match serde_json::from_slice::<Value>(&body) {
+                Ok(request) => state.handle(&request),
+                Err(err) => json!({
+                    "jsonrpc": "2.0",
+                    "id": null,
+                    "error": { "code": -32700, "message": "Parse error", "data": err.to_string() },
+                }),
+            }
+        };
+
+        let body = response.to_string();
+        write!(
+            writer,
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )?;
+        writer.flush()?;
+
+        if close {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(mock: &MockSandbox, method: &str) -> Value {
+        call_with(mock, method, json!({}))
+    }
+
+    fn call_with(mock: &MockSandbox, method: &str, params: Value) -> Value {
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        let response = ureq::post(&mock.rpc_addr)
+            .send_string(&request.to_string())
+            .unwrap();
+        serde_json::from_reader(response.into_reader()).unwrap()
+    }
+
+    #[test]
+    fn status_probes_are_not_recorded() {
+        let mock = MockSandbox::start().unwrap();
+
+        let response = ureq::get(&format!("{}/status", mock.rpc_addr))
+            .call()
+            .unwrap();
+        let status: Value = serde_json::from_reader(response.into_reader()).unwrap();
+        assert_eq!(status["chain_id"], "sandbox");
+        assert!(mock.requests().is_empty());
+
+        assert_eq!(call(&mock, "status")["result"]["chain_id"], "sandbox");
+        assert_eq!(mock.requests_for("status").len(), 1);
+    }
+
+    #[test]
+    fn unregistered_methods_fail() {
+        let mock = MockSandbox::start().unwrap();
+        mock.respond_with("gas_price", json!({ "gas_price": "100000000" }));
+
+        assert_eq!(call(&mock, "gas_price")["result"]["gas_price"], "100000000");
+        assert_eq!(call(&mock, "block")["error"]["code"], -32601);
+        let methods: Vec<String> = mock.requests().into_iter().map(|r| r.method).collect();
+        assert_eq!(methods, ["gas_price", "block"]);
+    }
+
+    #[test]
+    fn query_and_broadcast_have_canned_answers() {
+        let mock = MockSandbox::start().unwrap();
+
+        let query =
+            |request_type: &str| call_with(&mock, "query", json!({ "request_type": request_type }));
+        assert_eq!(query("view_account")["result"]["amount"], "0");
+        assert_eq!(query("view_access_key")["result"]["nonce"], 0);
+        assert_eq!(query("call_function")["result"]["result"], json!([]));
+        assert_eq!(query("view_state")["error"]["code"], -32000);
+
+        let outcome = call_with(&mock, "broadcast_tx_commit", json!(["AAAA"]));
+        assert_eq!(outcome["result"]["status"], json!({ "SuccessValue": "" }));
+        assert_eq!(
+            mock.requests_for("broadcast_tx_commit")[0].params,
+            json!(["AAAA"])
+        );
+    }
+
+    #[test]
+    fn registered_handlers_answer_from_the_params() {
+        let mock = MockSandbox::start().unwrap();
+        mock.register("query", |params| match params["account_id"].as_str() {
+            Some("alice.near") => Ok(json!({ "amount": "100" })),
+            Some(account_id) => Err(json!({
+                "name": "HANDLER_ERROR",
+                "cause": { "name": "UNKNOWN_ACCOUNT", "info": { "requested_account_id": account_id } },
+            })),
+            None => Err(json!({ "name": "REQUEST_VALIDATION_ERROR" })),
+        });
+
+        let query = |params: Value| call_with(&mock, "query", params);
+        assert_eq!(
+            query(json!({ "account_id": "alice.near" }))["result"]["amount"],
+            "100"
+        );
+        let error = query(json!({ "account_id": "bob.near" }))["error"].clone();
+        assert_eq!(error["cause"]["info"]["requested_account_id"], "bob.near");
+        assert_eq!(
+            query(json!({}))["error"]["name"],
+            "REQUEST_VALIDATION_ERROR"
+        );
+        assert_eq!(mock.requests_for("query").len(), 3);
+    }
+}
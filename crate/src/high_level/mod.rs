@@ -1,45 +1,61 @@
-use std::net::SocketAddrV4;
-use std::time::Duration;
-use std::{fs::File, net::Ipv4Addr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
 
-use fs2::FileExt;
 use tempfile::TempDir;
-use tokio::net::TcpListener;
 use tokio::process::Child;
 use tracing::info;
 
+use self::startup::RunAttempt;
+use crate::launcher::{self, LocalLauncher, SandboxLauncher};
+
 pub mod config;
-pub use config::{GenesisAccount, SandboxConfig};
+mod config_keys;
+mod credentials;
+mod genesis;
+pub(crate) mod logs;
+mod metrics;
+mod mock;
+mod node_config;
+mod port;
+mod presets;
+mod rpc;
+mod shards;
+mod signer;
+pub(crate) mod startup;
+mod stream;
+pub use config::{GenesisAccessKey, GenesisAccount, SandboxConfig};
+pub use config_keys::ConfigKeyCheck;
+pub use credentials::{
+    default_credentials_dir, export_credentials, load_credentials, DEFAULT_CREDENTIALS_NETWORK,
+};
+pub use logs::NODE_LOG_TARGET;
+pub use metrics::{MetricSample, Metrics, ProcessStats};
+pub use mock::{MockRequest, MockSandbox};
+pub use node_config::NodeConfig;
+pub(crate) use port::acquire_port;
+pub use port::PortLock;
+pub use presets::GenesisPreset;
+pub use shards::ShardLayout;
+pub use signer::{AccessKey, AccessKeyPermission, Action, PublicKey, SignedTransaction, Signer};
+pub use stream::{BlockStream, BlockStreamOptions, Finality, StreamedBlock, StreamedChunk};
 
 // Must be an IP address as `neard` expects socket address for network address.
-const DEFAULT_RPC_HOST: &str = "127.0.0.1";
-
-fn rpc_socket(port: u16) -> String {
-    format!("{DEFAULT_RPC_HOST}:{}", port)
-}
+// Important to use localhost as using 0.0.0.0 leads to users getting brief firewall popups to
+// allow inbound connections on MacOS.
+pub(crate) const DEFAULT_BIND_ADDR: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
 
-/// Request an unused port from the OS.
-async fn pick_unused_port() -> anyhow::Result<u16> {
-    // Port 0 means the OS gives us an unused port
-    // Important to use localhost as using 0.0.0.0 leads to users getting brief firewall popups to
-    // allow inbound connections on MacOS.
-    let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0);
-    let listener = TcpListener::bind(addr).await?;
-    let port = listener.local_addr()?.port();
-    Ok(port)
-}
+/// How long to wait for a killed sandbox to exit before its ports are given up.
+pub(crate) const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 
-/// Acquire an unused port and lock it for the duration until the sandbox server has
-/// been started.
-async fn acquire_unused_port() -> anyhow::Result<(u16, File)> {
-    loop {
-        let port = pick_unused_port().await?;
-        let lockpath = std::env::temp_dir().join(format!("near-sandbox-port{}.lock", port));
-        let lockfile = File::create(lockpath)?;
-        if lockfile.try_lock_exclusive().is_ok() {
-            break Ok((port, lockfile));
-        }
-    }
+/// URL clients should use to reach an RPC server listening on `addr`. Servers bound to all
+/// interfaces are reached through the loopback address of the same IP family.
+pub(crate) fn rpc_url(addr: SocketAddr) -> String {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    format!("http://{}", SocketAddr::new(ip, addr.port()))
 }
 
 /// An sandbox instance that can be used to launch local near network to test against.
@@ -50,9 +66,10 @@ async fn acquire_unused_port() -> anyhow::Result<(u16, File)> {
 pub struct Sandbox {
     pub home_dir: TempDir,
     pub rpc_addr: String,
-    pub rpc_port_lock: File,
-    pub net_port_lock: File,
+    pub rpc_port_lock: PortLock,
+    pub net_port_lock: PortLock,
     process: Child,
+    blocks_per_second: Option<f64>,
 }
 
 impl Sandbox {
@@ -94,67 +111,154 @@ impl Sandbox {
         config: SandboxConfig,
         version: &str,
     ) -> anyhow::Result<Self> {
-        suppress_sandbox_logs_if_required();
-        let home_dir = Self::init_home_dir_with_version(version).await?;
+        Self::start_sandbox_with_launcher(config, version, &LocalLauncher::default()).await
+    }
 
-        let (rpc_port, rpc_port_lock) = acquire_unused_port().await?;
-        let (net_port, net_port_lock) = acquire_unused_port().await?;
+    /// Start a new sandbox with a custom configuration and specific near-sandbox-utils version,
+    /// spawning the node through a custom launcher.
+    ///
+    /// # Arguments
+    /// * `config` - custom configuration for the sandbox
+    /// * `version` - the version of the near-sandbox-utils to use
+    /// * `launcher` - the launcher used to run `near-sandbox`, see [`SandboxLauncher`]
+    ///
+    pub async fn start_sandbox_with_launcher(
+        config: SandboxConfig,
+        version: &str,
+        launcher: &dyn SandboxLauncher,
+    ) -> anyhow::Result<Self> {
+        Self::install(launcher, version).await?;
+        let log_vars = config.log_vars();
+        let home_dir = Self::init_home_dir_with_version(version, launcher, &log_vars).await?;
+        startup::configure_home(home_dir.path(), &config)?;
 
-        let rpc_addr = rpc_socket(rpc_port);
-        let net_addr = rpc_socket(net_port);
+        let mut attempt = 1;
+        loop {
+            let run = RunAttempt::prepare(launcher, version, home_dir.path(), &config, &log_vars)?;
+            let mut child = crate::spawn(run.command, "run")?;
+            if config.forward_logs {
+                forward_logs(&mut child, run.rpc_port_lock.port());
+            }
 
-        config::set_sandbox_configs_with_config(&home_dir, &config)?;
-        config::set_sandbox_genesis_with_config(&home_dir, &config)?;
+            info!(target: "sandbox", "Started up sandbox at {} with pid={:?}", run.rpc_socket, child.id());
 
-        let options = &[
-            "--home",
-            home_dir.path().to_str().expect("home_dir is valid utf8"),
-            "run",
-            "--rpc-addr",
-            &rpc_addr,
-            "--network-addr",
-            &net_addr,
-        ];
+            let rpc_addr = rpc_url(run.rpc_socket);
 
-        let child = crate::run_with_options_with_version(options, version)?;
+            match Self::wait_until_ready(&rpc_addr, &mut child).await {
+                Ok(()) => {
+                    let mut sandbox = Self {
+                        home_dir,
+                        rpc_addr,
+                        rpc_port_lock: run.rpc_port_lock,
+                        net_port_lock: run.net_port_lock,
+                        process: child,
+                        blocks_per_second: None,
+                    };
+                    if let Some(window) = config.measure_block_rate {
+                        let rate = sandbox.measure_blocks_per_second(window).await;
+                        sandbox.blocks_per_second = startup::measured_block_rate(rate);
+                    }
+                    return Ok(sandbox);
+                }
+                Err(err) => {
+                    let exited = matches!(child.try_wait(), Ok(Some(_)));
+                    if startup::should_retry(&config, attempt, exited, &err) {
+                        attempt += 1;
+                        continue;
+                    }
 
-        info!(target: "sandbox", "Started up sandbox at localhost:{} with pid={:?}", rpc_port, child.id());
+                    let _ = child.kill().await;
+                    return Err(err);
+                }
+            }
+        }
+    }
 
-        let rpc_addr = format!("http://{rpc_addr}");
+    /// Blocks per second the node produced right after startup, if measured because of
+    /// [`SandboxConfig::measure_block_rate`].
+    pub fn blocks_per_second(&self) -> Option<f64> {
+        self.blocks_per_second
+    }
 
-        Self::wait_until_ready(&rpc_addr).await?;
+    /// Measure the rate at which the node produces blocks over the given `window`.
+    pub async fn measure_blocks_per_second(&self, window: Duration) -> anyhow::Result<f64> {
+        let start = (self.latest_block_height().await?, Instant::now());
+        tokio::time::sleep(window).await;
+        let end = (self.latest_block_height().await?, Instant::now());
+        Ok(startup::block_rate(start, end))
+    }
 
-        Ok(Self {
-            home_dir,
-            rpc_addr,
-            rpc_port_lock,
-            net_port_lock,
-            process: child,
-        })
+    async fn latest_block_height(&self) -> anyhow::Result<u64> {
+        let status = reqwest::get(format!("{}/status", self.rpc_addr))
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        startup::latest_block_height(&status)
     }
 
-    async fn init_home_dir_with_version(version: &str) -> anyhow::Result<TempDir> {
-        let home_dir = tempfile::tempdir()?;
+    /// Stream the blocks of the sandbox along with their chunks' transactions and, unless
+    /// disabled in `options`, the outcomes of the transactions and their receipts.
+    /// Must be called within a tokio runtime.
+    pub fn block_stream(&self, options: BlockStreamOptions) -> BlockStream {
+        BlockStream::new(self.rpc_addr.clone(), options)
+    }
 
-        let output = crate::init_with_version(&home_dir, version)?
-            .wait_with_output()
+    /// Scrape the Prometheus metrics of the node.
+    pub async fn metrics(&self) -> anyhow::Result<Metrics> {
+        let text = reqwest::get(format!("{}/metrics", self.rpc_addr))
+            .await?
+            .error_for_status()?
+            .text()
             .await?;
+        Metrics::parse(&text)
+    }
+
+    /// Resources currently used by the node process. Only available on Linux.
+    pub fn process_stats(&self) -> anyhow::Result<ProcessStats> {
+        let pid = self
+            .process
+            .id()
+            .ok_or_else(|| anyhow::anyhow!("sandbox process has already exited"))?;
+        ProcessStats::of_process(pid)
+    }
+
+    /// Install the binary `launcher` runs on a blocking thread, as downloading it with retries
+    /// and backoff would stall the runtime otherwise. Building the commands finds it installed.
+    async fn install(launcher: &dyn SandboxLauncher, version: &str) -> anyhow::Result<()> {
+        let Some(download) = launcher.download_config()? else {
+            return Ok(());
+        };
+        let version = version.to_string();
+        tokio::task::spawn_blocking(move || {
+            crate::ensure_sandbox_bin_with_config(&version, &download)
+        })
+        .await??;
+        Ok(())
+    }
+
+    async fn init_home_dir_with_version(
+        version: &str,
+        launcher: &dyn SandboxLauncher,
+        log_vars: &[(String, String)],
+    ) -> anyhow::Result<TempDir> {
+        let home_dir = tempfile::tempdir()?;
+        let command = launcher::init_command(launcher, version, home_dir.path(), log_vars)?;
+        let output = crate::spawn(command, "init")?.wait_with_output().await?;
         info!(target: "sandbox", "sandbox init: {:?}", output);
 
         Ok(home_dir)
     }
 
-    async fn wait_until_ready(rpc: &str) -> anyhow::Result<()> {
-        let timeout_secs = match std::env::var("NEAR_RPC_TIMEOUT_SECS") {
-            Ok(secs) => secs
-                .parse::<u64>()
-                .expect("Failed to parse NEAR_RPC_TIMEOUT_SECS"),
-            Err(_) => 10,
-        };
+    async fn wait_until_ready(rpc: &str, child: &mut Child) -> anyhow::Result<()> {
+        let timeout_secs = startup::rpc_timeout_secs();
 
         let mut interval = tokio::time::interval(Duration::from_millis(500));
         for _ in 0..timeout_secs * 2 {
             interval.tick().await;
+            if let Some(status) = child.try_wait()? {
+                anyhow::bail!("Sandbox exited during startup with {status}");
+            }
             let response = reqwest::get(format!("{}/status", rpc)).await;
             if response.is_ok() {
                 return Ok(());
@@ -166,6 +270,17 @@ impl Sandbox {
     }
 }
 
+/// Forward the output of the sandbox into `tracing` from background tasks.
+fn forward_logs(child: &mut Child, port: u16) {
+    let pid = child.id();
+    if let Some(stdout) = child.stdout.take() {
+        tokio::spawn(logs::forward_async(stdout, pid, port));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        tokio::spawn(logs::forward_async(stderr, pid, port));
+    }
+}
+
 impl Drop for Sandbox {
     fn drop(&mut self) {
         info!(
@@ -175,23 +290,12 @@ impl Drop for Sandbox {
         );
 
         self.process.start_kill().expect("failed to kill sandbox");
-        let _ = self.process.try_wait();
-    }
-}
 
-/// Turn off neard-sandbox logs by default. Users can turn them back on with
-/// NEAR_ENABLE_SANDBOX_LOG=1 and specify further parameters with the custom
-/// NEAR_SANDBOX_LOG for higher levels of specificity. NEAR_SANDBOX_LOG args
-/// will be forward into RUST_LOG environment variable as to not conflict
-/// with similar named log targets.
-fn suppress_sandbox_logs_if_required() {
-    if let Ok(val) = std::env::var("NEAR_ENABLE_SANDBOX_LOG") {
-        if val != "0" {
-            return;
+        // Hold on to the ports until the process is gone, so they aren't handed out to a new
+        // sandbox while this one still has them bound.
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+        while matches!(self.process.try_wait(), Ok(None)) && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
         }
     }
-
-    // non-exhaustive list of targets to suppress, since choosing a default LogLevel
-    // does nothing in this case, since nearcore seems to be overriding it somehow:
-    std::env::set_var("NEAR_SANDBOX_LOG", "near=error,stats=error,network=error");
 }
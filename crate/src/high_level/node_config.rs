@@ -0,0 +1,169 @@
+//! Typed access to the config.json settings tests change most often.
+//!
+//! Only a handful of knobs are covered on purpose, see the note in [`config`](super::config)
+//! on why we don't mirror nearcore's config structs. Everything else still goes through
+//! [`SandboxConfig::additional_config`](super::SandboxConfig::additional_config).
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// Frequently used settings of the node's config.json. Settings left as `None` keep the
+/// value `near-sandbox init` generated.
+///
+/// The RPC payload limit and the number of open files are set through
+/// [`SandboxConfig::max_payload_size`](super::SandboxConfig::max_payload_size) and
+/// [`SandboxConfig::max_open_files`](super::SandboxConfig::max_open_files).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NodeConfig {
+    /// Shards the node tracks, `tracked_shards`.
+    pub tracked_shards: Option<Vec<u64>>,
+    /// Number of epochs of data kept before garbage collection, `gc_num_epochs_to_keep`.
+    pub gc_num_epochs_to_keep: Option<u64>,
+    /// Keep all the data instead of garbage collecting it, `archive`.
+    pub archive: Option<bool>,
+    /// Keep the trie changes of every block, which archival nodes need to serve state at old
+    /// heights, `save_trie_changes`.
+    pub save_trie_changes: Option<bool>,
+    /// Minimum time between two blocks, `consensus.min_block_production_delay`.
+    pub min_block_production_delay: Option<Duration>,
+    /// Maximum time between two blocks, `consensus.max_block_production_delay`.
+    pub max_block_production_delay: Option<Duration>,
+    /// Maximum time to wait for a block before producing one, `consensus.max_block_wait_delay`.
+    pub max_block_wait_delay: Option<Duration>,
+    /// Fraction of the minimum block production delay to wait for chunks before producing
+    /// a block without them, as `(numerator, denominator)`, `consensus.chunk_wait_mult`.
+    pub chunk_wait_mult: Option<(i32, i32)>,
+    /// How long `broadcast_tx_commit` and similar RPC methods wait for a result,
+    /// `rpc.polling_config.polling_timeout`.
+    pub rpc_polling_timeout: Option<Duration>,
+    /// Size of the trie caches in bytes, `store.trie_cache` and `store.view_trie_cache`.
+    pub trie_cache_max_bytes: Option<u64>,
+}
+
+/// nearcore's serialization of a `Duration`.
+fn duration(duration: Duration) -> Value {
+    json!({
+        "secs": duration.as_secs(),
+        "nanos": duration.subsec_nanos(),
+    })
+}
+
+impl NodeConfig {
+    /// Block production timing for the fastest block rate a single sandbox node keeps up
+    /// with reliably, instead of the delays tuned for real networks.
+    pub fn fast_block_production() -> Self {
+        Self {
+            min_block_production_delay: Some(Duration::from_millis(100)),
+            max_block_production_delay: Some(Duration::from_millis(300)),
+            max_block_wait_delay: Some(Duration::from_secs(1)),
+            // Relative to the shorter minimum delay, which is still plenty for chunks the node
+            // produces itself.
+            chunk_wait_mult: Some((1, 6)),
+            ..Self::default()
+        }
+    }
+
+    /// The settings as JSON to be merged into config.json.
+    pub(crate) fn to_json(&self) -> Value {
+        let mut config = json!({});
+        let mut set = |patch: Value| json_patch::merge(&mut config, &patch);
+
+        if let Some(tracked_shards) = &self.tracked_shards {
+            set(json!({ "tracked_shards": tracked_shards }));
+        }
+        if let Some(epochs) = self.gc_num_epochs_to_keep {
+            set(json!({ "gc_num_epochs_to_keep": epochs }));
+        }
+        if let Some(archive) = self.archive {
+            set(json!({ "archive": archive }));
+        }
+        if let Some(save_trie_changes) = self.save_trie_changes {
+            set(json!({ "save_trie_changes": save_trie_changes }));
+        }
+        if let Some(delay) = self.min_block_production_delay {
+            set(json!({ "consensus": { "min_block_production_delay": duration(delay) } }));
+        }
+        if let Some(delay) = self.max_block_production_delay {
+            set(json!({ "consensus": { "max_block_production_delay": duration(delay) } }));
+        }
+        if let Some(delay) = self.max_block_wait_delay {
+            set(json!({ "consensus": { "max_block_wait_delay": duration(delay) } }));
+        }
+        if let Some((numerator, denominator)) = self.chunk_wait_mult {
+            set(json!({ "consensus": { "chunk_wait_mult": [numerator, denominator] } }));
+        }
+        if let Some(timeout) = self.rpc_polling_timeout {
+            set(json!({ "rpc": { "polling_config": { "polling_timeout": duration(timeout) } } }));
+        }
+        if let Some(bytes) = self.trie_cache_max_bytes {
+            set(json!({
+                "store": {
+                    "trie_cache": { "default_max_bytes": bytes },
+                    "view_trie_cache": { "default_max_bytes": bytes },
+                }
+            }));
+        }
+
+        config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fast_block_production_only_sets_the_consensus_delays() {
+        assert_eq!(
+            NodeConfig::fast_block_production().to_json(),
+            json!({
+                "consensus": {
+                    "min_block_production_delay": { "secs": 0, "nanos": 100_000_000 },
+                    "max_block_production_delay": { "secs": 0, "nanos": 300_000_000 },
+                    "max_block_wait_delay": { "secs": 1, "nanos": 0 },
+                    "chunk_wait_mult": [1, 6],
+                }
+            })
+        );
+        assert_eq!(NodeConfig::default().to_json(), json!({}));
+    }
+
+    #[test]
+    fn every_setting_ends_up_in_config_json() {
+        let config = NodeConfig {
+            tracked_shards: Some(vec![0, 1]),
+            gc_num_epochs_to_keep: Some(7),
+            archive: Some(true),
+            save_trie_changes: Some(false),
+            min_block_production_delay: Some(Duration::from_millis(1500)),
+            max_block_production_delay: Some(Duration::from_secs(3)),
+            max_block_wait_delay: Some(Duration::from_nanos(5)),
+            chunk_wait_mult: Some((2, 3)),
+            rpc_polling_timeout: Some(Duration::from_secs(20)),
+            trie_cache_max_bytes: Some(1024),
+        };
+        assert_eq!(
+            config.to_json(),
+            json!({
+                "tracked_shards": [0, 1],
+                "gc_num_epochs_to_keep": 7,
+                "archive": true,
+                "save_trie_changes": false,
+                "consensus": {
+                    "min_block_production_delay": { "secs": 1, "nanos": 500_000_000 },
+                    "max_block_production_delay": { "secs": 3, "nanos": 0 },
+                    "max_block_wait_delay": { "secs": 0, "nanos": 5 },
+                    "chunk_wait_mult": [2, 3],
+                },
+                "rpc": { "polling_config": { "polling_timeout": { "secs": 20, "nanos": 0 } } },
+                "store": {
+                    "trie_cache": { "default_max_bytes": 1024 },
+                    "view_trie_cache": { "default_max_bytes": 1024 },
+                },
+            })
+        );
+    }
+}
@@ -0,0 +1,100 @@
+//! Reservation of the ports a sandbox listens on.
+//!
+//! A port is reserved by locking a `near-sandbox-port{port}.lock` file in the temp directory,
+//! which keeps cooperating sandboxes (also in other processes) off the port. On top of that the
+//! port stays bound by a listener until right before `neard` gets spawned, to keep the window in
+//! which a non-cooperating process can grab it as small as possible. The lock is held until the
+//! sandbox shuts down, after which the lock file gets removed again.
+
+use std::fs::{File, OpenOptions};
+use std::net::{IpAddr, TcpListener};
+use std::path::PathBuf;
+
+use anyhow::Context;
+use fs2::FileExt;
+
+use crate::install::same_file;
+
+/// A port reserved for a sandbox for as long as this value is alive.
+#[derive(Debug)]
+pub struct PortLock {
+    port: u16,
+    path: PathBuf,
+    file: File,
+    listener: Option<TcpListener>,
+}
+
+impl PortLock {
+    /// The reserved port.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Stop listening on the port so the sandbox can bind it. The lock itself is kept.
+    pub(crate) fn release_listener(&mut self) {
+        self.listener = None;
+    }
+
+    /// Take over the listener bound to the port, for serving on it in-process.
+    pub(crate) fn take_listener(&mut self) -> Option<TcpListener> {
+        self.listener.take()
+    }
+
+    /// Lock the port for this process, returning `None` if some other sandbox already holds it.
+    fn lock(port: u16) -> anyhow::Result<Option<Self>> {
+        let path = std::env::temp_dir().join(format!("near-sandbox-port{}.lock", port));
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        if file.try_lock_exclusive().is_err() {
+            return Ok(None);
+        }
+
+        // The previous owner might have removed the file between us opening and locking it,
+        // in which case the lock is on an orphaned file and someone else may lock a new one.
+        if !same_file(&file, &path) {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            port,
+            path,
+            file,
+            listener: None,
+        }))
+    }
+}
+
+impl Drop for PortLock {
+    fn drop(&mut self) {
+        // Remove the file while still holding the lock, see `same_file` for how others cope.
+        let _ = std::fs::remove_file(&self.path);
+        let _ = FileExt::unlock(&self.file);
+    }
+}
+
+/// Acquire a port on `ip` and reserve it for the sandbox. Picks an unused port unless a fixed
+/// `port` is requested, in which case it is an error if the port is already taken.
+pub(crate) fn acquire_port(ip: IpAddr, port: Option<u16>, name: &str) -> anyhow::Result<PortLock> {
+    let Some(port) = port else {
+        loop {
+            // Port 0 means the OS gives us an unused port
+            let listener = TcpListener::bind((ip, 0))
+                .with_context(|| format!("failed to pick an unused {name} port on {ip}"))?;
+            let port = listener.local_addr()?.port();
+            if let Some(mut lock) = PortLock::lock(port)? {
+                lock.listener = Some(listener);
+                return Ok(lock);
+            }
+        }
+    };
+
+    let mut lock = PortLock::lock(port)?
+        .ok_or_else(|| anyhow::anyhow!("{name} port {port} is already used by another sandbox"))?;
+    let listener = TcpListener::bind((ip, port))
+        .with_context(|| format!("{name} port {port} on {ip} is already in use"))?;
+    lock.listener = Some(listener);
+    Ok(lock)
+}
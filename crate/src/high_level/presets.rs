@@ -0,0 +1,99 @@
+//! Named sets of genesis parameters.
+//!
+//! `near-sandbox init --fast` generates a genesis meant to get a node going quickly, with gas
+//! limits, gas prices and an epoch length that differ from the real networks. The presets
+//! replace those parameters consistently, so gas-sensitive tests see the same limits and prices
+//! locally as in production.
+//!
+//! Fees and other runtime parameters aren't part of the genesis: they are determined by the
+//! protocol version the chain starts at. The presets start at the newest version the node
+//! binary supports, which is what `init` writes into the genesis. The default binary is the
+//! release running on mainnet, so that's the version mainnet runs. Another version can be set
+//! through [`SandboxConfig::additional_genesis`](super::SandboxConfig::additional_genesis),
+//! which is applied after the preset, though the node refuses to start on a newer one.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// Gas limit of a chunk on mainnet and testnet.
+const NETWORK_GAS_LIMIT: u64 = 1_000_000_000_000_000;
+const NETWORK_MIN_GAS_PRICE: u128 = 100_000_000;
+const NETWORK_MAX_GAS_PRICE: u128 = 10_000_000_000_000_000_000_000;
+/// Blocks in an epoch on mainnet and testnet, about 12 hours.
+const NETWORK_EPOCH_LENGTH: u64 = 43_200;
+const NETWORK_TRANSACTION_VALIDITY_PERIOD: u64 = 86_400;
+
+const FAST_LOCAL_EPOCH_LENGTH: u64 = 10;
+const FAST_LOCAL_TRANSACTION_VALIDITY_PERIOD: u64 = 100;
+
+/// Genesis parameters to start the sandbox with instead of the ones of `init --fast`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GenesisPreset {
+    /// Gas limit, gas price bounds and adjustment rate, epoch length, transaction validity
+    /// and inflation as on mainnet, at the newest protocol version of the node binary.
+    MainnetLike,
+    /// The parameters of testnet. Testnet uses the same genesis parameters as mainnet and only
+    /// runs a newer protocol version between a release and its adoption on mainnet, so this
+    /// matches [`GenesisPreset::MainnetLike`] and states which network a test mirrors. Run the
+    /// binary of the newer release to get its protocol version.
+    TestnetLike,
+    /// Mainnet gas limit and prices at the newest protocol version of the node binary, with
+    /// short epochs to quickly run through epoch changes and validator updates.
+    FastLocal,
+}
+
+impl GenesisPreset {
+    /// The parameters of the preset as JSON to be merged into genesis.json, for a node binary
+    /// whose newest protocol version is `protocol_version`.
+    pub(crate) fn to_json(self, protocol_version: u64) -> Value {
+        let (epoch_length, transaction_validity_period) = match self {
+            Self::MainnetLike | Self::TestnetLike => {
+                (NETWORK_EPOCH_LENGTH, NETWORK_TRANSACTION_VALIDITY_PERIOD)
+            }
+            Self::FastLocal => (
+                FAST_LOCAL_EPOCH_LENGTH,
+                FAST_LOCAL_TRANSACTION_VALIDITY_PERIOD,
+            ),
+        };
+
+        json!({
+            "protocol_version": protocol_version,
+            "gas_limit": NETWORK_GAS_LIMIT,
+            "min_gas_price": NETWORK_MIN_GAS_PRICE.to_string(),
+            "max_gas_price": NETWORK_MAX_GAS_PRICE.to_string(),
+            "gas_price_adjustment_rate": [1, 100],
+            "epoch_length": epoch_length,
+            "transaction_validity_period": transaction_validity_period,
+            "protocol_reward_rate": [1, 10],
+            "max_inflation_rate": [1, 20],
+            "num_blocks_per_year": 31_536_000,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_start_at_the_protocol_version_of_the_binary() {
+        for preset in [
+            GenesisPreset::MainnetLike,
+            GenesisPreset::TestnetLike,
+            GenesisPreset::FastLocal,
+        ] {
+            assert_eq!(preset.to_json(77)["protocol_version"], 77);
+        }
+    }
+
+    #[test]
+    fn testnet_uses_the_parameters_of_mainnet() {
+        let mainnet = GenesisPreset::MainnetLike.to_json(77);
+        assert_eq!(mainnet, GenesisPreset::TestnetLike.to_json(77));
+        assert_eq!(mainnet["epoch_length"], NETWORK_EPOCH_LENGTH);
+        assert_eq!(
+            GenesisPreset::FastLocal.to_json(77)["epoch_length"],
+            FAST_LOCAL_EPOCH_LENGTH
+        );
+    }
+}
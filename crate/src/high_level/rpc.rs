@@ -0,0 +1,79 @@
+//! Minimal JSON-RPC client for the node's RPC server.
+
+use serde_json::Value;
+
+/// An error returned by the RPC server in response to a request.
+#[derive(Debug, Clone)]
+pub(crate) struct RpcError {
+    pub(crate) method: String,
+    pub(crate) error: Value,
+}
+
+impl RpcError {
+    /// Name of the error's cause, e.g. `UNKNOWN_BLOCK`.
+    pub(crate) fn cause(&self) -> Option<&str> {
+        self.error["cause"]["name"].as_str()
+    }
+}
+
+impl std::fmt::Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "`{}` failed: {}", self.method, self.error)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+fn request(method: &str, params: Value) -> String {
+    serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": "dontcare",
+        "method": method,
+        "params": params,
+    })
+    .to_string()
+}
+
+fn result(method: &str, body: &str) -> anyhow::Result<Value> {
+    let mut response: Value = serde_json::from_str(body)
+        .map_err(|err| anyhow::anyhow!("invalid `{method}` response: {err}: {body}"))?;
+    if let Some(error) = response.get_mut("error") {
+        return Err(RpcError {
+            method: method.to_string(),
+            error: error.take(),
+        }
+        .into());
+    }
+    match response.get_mut("result") {
+        Some(result) => Ok(result.take()),
+        None => anyhow::bail!("invalid `{method}` response: {body}"),
+    }
+}
+
+/// Call `method` on the RPC server at `rpc_addr`. Errors returned by the server are reported
+/// as [`RpcError`].
+pub(crate) async fn call(rpc_addr: &str, method: &str, params: Value) -> anyhow::Result<Value> {
+    // Errors are reported in the body, which may come with a non-success status.
+    let body = reqwest::Client::new()
+        .post(rpc_addr)
+        .header("Content-Type", "application/json")
+        .body(request(method, params))
+        .send()
+        .await?
+        .text()
+        .await?;
+    result(method, &body)
+}
+
+/// Blocking version of [`call`].
+pub(crate) fn call_blocking(rpc_addr: &str, method: &str, params: Value) -> anyhow::Result<Value> {
+    let response = ureq::post(rpc_addr)
+        .set("Content-Type", "application/json")
+        .send_string(&request(method, params));
+    // Errors are reported in the body, which may come with a non-success status.
+    let body = match response {
+        Ok(response) | Err(ureq::Error::Status(_, response)) => response.into_string()?,
+        Err(err) => return Err(err.into()),
+    };
+    result(method, &body)
+}
@@ -0,0 +1,232 @@
+//! Multi-shard genesis layouts.
+//!
+//! nearcore assigns accounts to shards by comparing them against a sorted list of boundary
+//! accounts: shard `i` holds the accounts from boundary `i - 1` (inclusive) up to boundary
+//! `i` (exclusive). The layout gets written in the format of the layout `init` generated, so
+//! it matches what the node binary expects.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::genesis::validate_account_id;
+
+/// Boundaries generated for [`ShardLayout::NumShards`] start with one of these letters.
+const BOUNDARY_LETTERS: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
+
+/// How accounts get split into shards.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ShardLayout {
+    /// Split the account space into the given number of shards, at most 26, at doubled
+    /// letters spread evenly over the alphabet, e.g. `nn` for two shards. Accounts compare
+    /// byte-wise, so with two shards `alice`, `near` and `nft` end up in shard 0, while
+    /// `nz.near` and `sandbox` end up in shard 1.
+    NumShards(u64),
+    /// Split at the given accounts, which must be valid account IDs in ascending order.
+    /// `n` boundary accounts make `n + 1` shards.
+    BoundaryAccounts(Vec<String>),
+}
+
+impl ShardLayout {
+    /// The sorted boundary accounts of the layout.
+    pub fn boundary_accounts(&self) -> anyhow::Result<Vec<String>> {
+        match self {
+            Self::NumShards(num_shards) => {
+                let num_shards = *num_shards as usize;
+                if !(1..=BOUNDARY_LETTERS.len()).contains(&num_shards) {
+                    anyhow::bail!(
+                        "number of shards must be between 1 and {}, got {num_shards}",
+                        BOUNDARY_LETTERS.len()
+                    );
+                }
+                Ok((1..num_shards)
+                    .map(|i| {
+                        let letter = BOUNDARY_LETTERS[i * BOUNDARY_LETTERS.len() / num_shards];
+                        // Account IDs are at least two characters long, so the boundary is
+                        // the letter doubled. Accounts starting with the letter fall on either
+                        // side of it depending on their second character.
+                        String::from_utf8(vec![letter, letter]).expect("letters are ascii")
+                    })
+                    .collect())
+            }
+            Self::BoundaryAccounts(accounts) => {
+                let mut problems = Vec::new();
+                for account in accounts {
+                    if let Err(err) = validate_account_id(account) {
+                        problems.push(format!("`{account}` is not a valid account ID, {err}"));
+                    }
+                }
+                for pair in accounts.windows(2) {
+                    if pair[0] >= pair[1] {
+                        problems.push(format!("`{}` is not ordered after `{}`", pair[1], pair[0]));
+                    }
+                }
+                if !problems.is_empty() {
+                    anyhow::bail!(
+                        "invalid shard boundary accounts:\n  - {}",
+                        problems.join("\n  - ")
+                    );
+                }
+                Ok(accounts.clone())
+            }
+        }
+    }
+
+    /// Number of shards of the layout.
+    pub fn num_shards(&self) -> anyhow::Result<u64> {
+        Ok(self.boundary_accounts()?.len() as u64 + 1)
+    }
+
+    /// Replace the layout of `genesis`, along with the per-shard seat counts that have to match
+    /// it. The variant and version of the layout `init` wrote are kept.
+    pub(crate) fn apply(&self, genesis: &mut Value) -> anyhow::Result<()> {
+        let boundary_accounts = self.boundary_accounts()?;
+        let num_shards = boundary_accounts.len() + 1;
+        let shard_ids: Vec<u64> = (0..num_shards as u64).collect();
+
+        let current = &genesis["shard_layout"];
+        let version = current
+            .as_object()
+            .and_then(|layout| layout.values().next())
+            .and_then(|layout| layout["version"].as_u64())
+            .unwrap_or(0);
+        let shard_layout = if current.get("V2").is_some() {
+            let index_map: serde_json::Map<String, Value> = shard_ids
+                .iter()
+                .map(|id| (id.to_string(), json!(id)))
+                .collect();
+            json!({
+                "V2": {
+                    "boundary_accounts": boundary_accounts,
+                    "shard_ids": shard_ids,
+                    "id_to_index_map": index_map,
+                    "index_to_id_map": index_map,
+                    "shards_split_map": null,
+                    "shards_parent_map": null,
+                    "version": version,
+                }
+            })
+        } else {
+            json!({
+                "V1": {
+                    "boundary_accounts": boundary_accounts,
+                    "shards_split_map": null,
+                    "to_parent_shard_map": null,
+                    "version": version,
+                }
+            })
+        };
+        let block_producer_seats = genesis["num_block_producer_seats"].as_u64().unwrap_or(1);
+
+        genesis["shard_layout"] = shard_layout;
+        genesis["num_block_producer_seats_per_shard"] =
+            json!(vec![block_producer_seats; num_shards]);
+        genesis["avg_hidden_validator_seats_per_shard"] = json!(vec![0; num_shards]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn num_shards_split_at_doubled_letters() {
+        assert!(ShardLayout::NumShards(1)
+            .boundary_accounts()
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            ShardLayout::NumShards(2).boundary_accounts().unwrap(),
+            ["nn"]
+        );
+        for account in ["alice", "near", "nearx", "nft"] {
+            assert!(account < "nn", "{} should be in shard 0", account);
+        }
+        for account in ["nz.near", "sandbox"] {
+            assert!(account >= "nn", "{} should be in shard 1", account);
+        }
+        assert_eq!(
+            ShardLayout::NumShards(3).boundary_accounts().unwrap(),
+            ["ii", "rr"]
+        );
+
+        let boundaries = ShardLayout::NumShards(26).boundary_accounts().unwrap();
+        assert_eq!(boundaries.len(), 25);
+        assert_eq!(boundaries.first().unwrap(), "bb");
+        assert_eq!(boundaries.last().unwrap(), "zz");
+        // Generated boundaries have to pass the same checks as explicit ones.
+        assert_eq!(
+            ShardLayout::BoundaryAccounts(boundaries.clone())
+                .boundary_accounts()
+                .unwrap(),
+            boundaries
+        );
+    }
+
+    #[test]
+    fn num_shards_out_of_range_is_rejected() {
+        for num_shards in [0, 27] {
+            let err = ShardLayout::NumShards(num_shards)
+                .boundary_accounts()
+                .unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!(
+                    "number of shards must be between 1 and 26, got {}",
+                    num_shards
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn boundary_accounts_must_be_valid_and_ascending() {
+        let layout = ShardLayout::BoundaryAccounts(vec!["bob".into(), "alice".into()]);
+        assert_eq!(
+            layout.boundary_accounts().unwrap_err().to_string(),
+            "invalid shard boundary accounts:\n  - `alice` is not ordered after `bob`"
+        );
+
+        let layout = ShardLayout::BoundaryAccounts(vec!["alice".into(), "Bob".into()]);
+        let err = layout.boundary_accounts().unwrap_err().to_string();
+        assert!(err.contains("`Bob` is not a valid account ID"), "{}", err);
+        assert!(
+            err.contains("`Bob` is not ordered after `alice`"),
+            "{}",
+            err
+        );
+
+        let layout = ShardLayout::BoundaryAccounts(vec!["alice".into(), "alice".into()]);
+        assert!(layout.boundary_accounts().is_err());
+
+        let layout = ShardLayout::BoundaryAccounts(vec!["alice".into(), "bob.near".into()]);
+        assert_eq!(layout.num_shards().unwrap(), 3);
+    }
+
+    #[test]
+    fn apply_keeps_the_layout_variant_and_version() {
+        let mut genesis = json!({
+            "num_block_producer_seats": 2,
+            "shard_layout": { "V2": { "boundary_accounts": [], "version": 3 } },
+        });
+        ShardLayout::NumShards(2).apply(&mut genesis).unwrap();
+        let layout = &genesis["shard_layout"]["V2"];
+        assert_eq!(layout["boundary_accounts"], json!(["nn"]));
+        assert_eq!(layout["shard_ids"], json!([0, 1]));
+        assert_eq!(layout["id_to_index_map"], json!({ "0": 0, "1": 1 }));
+        assert_eq!(layout["version"], 3);
+        assert_eq!(genesis["num_block_producer_seats_per_shard"], json!([2, 2]));
+        assert_eq!(
+            genesis["avg_hidden_validator_seats_per_shard"],
+            json!([0, 0])
+        );
+
+        let mut genesis = json!({ "shard_layout": { "V1": { "version": 1 } } });
+        ShardLayout::NumShards(3).apply(&mut genesis).unwrap();
+        assert_eq!(
+            genesis["shard_layout"]["V1"]["boundary_accounts"],
+            json!(["ii", "rr"])
+        );
+        assert_eq!(genesis["shard_layout"]["V1"]["version"], 1);
+    }
+}
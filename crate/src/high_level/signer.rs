@@ -0,0 +1,471 @@
+//! Minimal transaction signing for sandbox accounts.
+//!
+//! This only covers what's needed to set up a sandbox without pulling in a full NEAR SDK:
+//! building a transaction out of a handful of actions, borsh-serializing it the same way
+//! nearcore does and signing it with an ed25519 key of a [`GenesisAccount`].
+
+use std::convert::{TryFrom, TryInto};
+
+use base64::Engine;
+use borsh::BorshSerialize;
+use ed25519_dalek::{Signer as _, SigningKey};
+use sha2::{Digest, Sha256};
+
+use super::config::GenesisAccount;
+use super::rpc;
+
+/// Decode an `ed25519:<base58>` key into its raw bytes.
+pub(crate) fn decode_key<const N: usize>(key: &str) -> anyhow::Result<[u8; N]> {
+    let data = key
+        .strip_prefix("ed25519:")
+        .ok_or_else(|| anyhow::anyhow!("key `{key}` is not an ed25519 key"))?;
+    let bytes = bs58::decode(data)
+        .into_vec()
+        .map_err(|err| anyhow::anyhow!("key `{key}` is not valid base58: {err}"))?;
+    let len = bytes.len();
+    bytes
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("key `{key}` has {len} bytes, expected {N}"))
+}
+
+/// An ed25519 public key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, BorshSerialize)]
+pub enum PublicKey {
+    Ed25519([u8; 32]),
+}
+
+impl std::str::FromStr for PublicKey {
+    type Err = anyhow::Error;
+
+    fn from_str(key: &str) -> anyhow::Result<Self> {
+        Ok(Self::Ed25519(decode_key(key)?))
+    }
+}
+
+impl std::fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ed25519(key) => write!(f, "ed25519:{}", bs58::encode(key).into_string()),
+        }
+    }
+}
+
+/// What an access key is allowed to do.
+#[derive(Debug, Clone, PartialEq, Eq, BorshSerialize, serde::Serialize, serde::Deserialize)]
+pub enum AccessKeyPermission {
+    /// Only calling `method_names` (or any method if empty) on `receiver_id`, paying at most
+    /// `allowance` yoctoNEAR of gas fees (or unlimited if `None`).
+    FunctionCall {
+        allowance: Option<u128>,
+        receiver_id: String,
+        method_names: Vec<String>,
+    },
+    FullAccess,
+}
+
+/// An access key as added by [`Action::AddKey`].
+#[derive(Debug, Clone, PartialEq, Eq, BorshSerialize, serde::Serialize, serde::Deserialize)]
+pub struct AccessKey {
+    pub nonce: u64,
+    pub permission: AccessKeyPermission,
+}
+
+/// The subset of transaction actions supported by the [`Signer`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    CreateAccount,
+    DeployContract {
+        code: Vec<u8>,
+    },
+    FunctionCall {
+        method_name: String,
+        args: Vec<u8>,
+        gas: u64,
+        deposit: u128,
+    },
+    Transfer {
+        deposit: u128,
+    },
+    AddKey {
+        public_key: PublicKey,
+        access_key: AccessKey,
+    },
+}
+
+// Serialized by hand to keep the tags of nearcore's `Action` enum, which has more variants.
+impl BorshSerialize for Action {
+    fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        match self {
+            Self::CreateAccount => 0u8.serialize(writer),
+            Self::DeployContract { code } => {
+                1u8.serialize(writer)?;
+                code.serialize(writer)
+            }
+            Self::FunctionCall {
+                method_name,
+                args,
+                gas,
+                deposit,
+            } => {
+                2u8.serialize(writer)?;
+                method_name.serialize(writer)?;
+                args.serialize(writer)?;
+                gas.serialize(writer)?;
+                deposit.serialize(writer)
+            }
+            Self::Transfer { deposit } => {
+                3u8.serialize(writer)?;
+                deposit.serialize(writer)
+            }
+            Self::AddKey {
+                public_key,
+                access_key,
+            } => {
+                5u8.serialize(writer)?;
+                public_key.serialize(writer)?;
+                access_key.serialize(writer)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, BorshSerialize)]
+struct Transaction {
+    signer_id: String,
+    public_key: PublicKey,
+    nonce: u64,
+    receiver_id: String,
+    block_hash: [u8; 32],
+    actions: Vec<Action>,
+}
+
+#[derive(Debug, Clone, BorshSerialize)]
+enum Signature {
+    Ed25519([u8; 64]),
+}
+
+/// A transaction signed by a [`Signer`], ready to be broadcast.
+#[derive(Debug, Clone)]
+pub struct SignedTransaction {
+    transaction: Transaction,
+    signature: Signature,
+    hash: [u8; 32],
+}
+
+impl SignedTransaction {
+    /// Base58 encoded hash of the transaction, as used by the RPC to identify it.
+    pub fn hash(&self) -> String {
+        bs58::encode(self.hash).into_string()
+    }
+
+    /// Borsh serialization of the signed transaction.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = borsh::to_vec(&self.transaction).expect("serializing to vec never fails");
+        self.signature
+            .serialize(&mut bytes)
+            .expect("serializing to vec never fails");
+        bytes
+    }
+
+    /// Base64 of the borsh serialization, the format the `broadcast_tx_*` RPC methods expect.
+    pub fn to_base64(&self) -> String {
+        base64::engine::general_purpose::STANDARD.encode(self.to_bytes())
+    }
+}
+
+/// Signs transactions on behalf of an account with one of its ed25519 keys.
+#[derive(Clone)]
+pub struct Signer {
+    account_id: String,
+    public_key: PublicKey,
+    signing_key: SigningKey,
+}
+
+impl std::fmt::Debug for Signer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Signer")
+            .field("account_id", &self.account_id)
+            .field("public_key", &self.public_key.to_string())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Signer {
+    /// Create a signer for `account_id` from an `ed25519:<base58>` secret key as found in
+    /// NEAR key files, i.e. the 32 bytes secret key followed by the 32 bytes public key.
+    pub fn from_secret_key(
+        account_id: impl Into<String>,
+        secret_key: &str,
+    ) -> anyhow::Result<Self> {
+        let keypair = decode_key::<64>(secret_key)?;
+        let signing_key = SigningKey::from_keypair_bytes(&keypair)
+            .map_err(|err| anyhow::anyhow!("invalid ed25519 secret key: {err}"))?;
+        let public_key = PublicKey::Ed25519(signing_key.verifying_key().to_bytes());
+
+        Ok(Self {
+            account_id: account_id.into(),
+            public_key,
+            signing_key,
+        })
+    }
+
+    /// Create a signer using the full access key of a genesis account.
+    pub fn from_genesis_account(account: &GenesisAccount) -> anyhow::Result<Self> {
+        Self::from_secret_key(account.account_id.clone(), &account.private_key)
+    }
+
+    pub fn account_id(&self) -> &str {
+        &self.account_id
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        self.public_key
+    }
+
+    /// Build and sign a transaction. `nonce` has to be larger than the current nonce of the
+    /// access key and `block_hash` the base58 hash of a recent block.
+    pub fn sign(
+        &self,
+        nonce: u64,
+        receiver_id: impl Into<String>,
+        block_hash: &str,
+        actions: Vec<Action>,
+    ) -> anyhow::Result<SignedTransaction> {
+        let block_hash = bs58::decode(block_hash)
+            .into_vec()
+            .ok()
+            .and_then(|hash| <[u8; 32]>::try_from(hash).ok())
+            .ok_or_else(|| anyhow::anyhow!("invalid block hash `{block_hash}`"))?;
+
+        let transaction = Transaction {
+            signer_id: self.account_id.clone(),
+            public_key: self.public_key,
+            nonce,
+            receiver_id: receiver_id.into(),
+            block_hash,
+            actions,
+        };
+
+        let bytes = borsh::to_vec(&transaction)?;
+        let hash: [u8; 32] = Sha256::digest(&bytes).into();
+        let signature = Signature::Ed25519(self.signing_key.sign(&hash).to_bytes());
+
+        Ok(SignedTransaction {
+            transaction,
+            signature,
+            hash,
+        })
+    }
+}
+
+impl TryFrom<&GenesisAccount> for Signer {
+    type Error = anyhow::Error;
+
+    fn try_from(account: &GenesisAccount) -> anyhow::Result<Self> {
+        Self::from_genesis_account(account)
+    }
+}
+
+impl Signer {
+    /// Sign `actions` with the next nonce of the key and submit them to the RPC server at
+    /// `rpc_addr` via `broadcast_tx_commit`, waiting until the transaction is executed.
+    ///
+    /// Returns the final execution outcome, or an error if the transaction failed.
+    pub async fn send(
+        &self,
+        rpc_addr: &str,
+        receiver_id: impl Into<String>,
+        actions: Vec<Action>,
+    ) -> anyhow::Result<serde_json::Value> {
+        let access_key = rpc::call(rpc_addr, "query", access_key_query(self)).await?;
+        let (nonce, block_hash) = next_nonce_and_block_hash(&access_key)?;
+        let transaction = self.sign(nonce, receiver_id, &block_hash, actions)?;
+
+        let params = serde_json::json!([transaction.to_base64()]);
+        let outcome = rpc::call(rpc_addr, "broadcast_tx_commit", params).await?;
+        check_outcome(&transaction, outcome)
+    }
+
+    /// Blocking version of [`Signer::send`].
+    pub fn send_blocking(
+        &self,
+        rpc_addr: &str,
+        receiver_id: impl Into<String>,
+        actions: Vec<Action>,
+    ) -> anyhow::Result<serde_json::Value> {
+        let access_key = rpc::call_blocking(rpc_addr, "query", access_key_query(self))?;
+        let (nonce, block_hash) = next_nonce_and_block_hash(&access_key)?;
+        let transaction = self.sign(nonce, receiver_id, &block_hash, actions)?;
+
+        let params = serde_json::json!([transaction.to_base64()]);
+        let outcome = rpc::call_blocking(rpc_addr, "broadcast_tx_commit", params)?;
+        check_outcome(&transaction, outcome)
+    }
+}
+
+fn check_outcome(
+    transaction: &SignedTransaction,
+    outcome: serde_json::Value,
+) -> anyhow::Result<serde_json::Value> {
+    if let Some(failure) = outcome["status"].get("Failure") {
+        anyhow::bail!("transaction {} failed: {failure}", transaction.hash());
+    }
+    Ok(outcome)
+}
+
+/// Params of the `view_access_key` query for the signer's key. Optimistic finality sees the
+/// nonces of transactions which were just executed, the final block lags a couple of blocks
+/// behind and would hand out a nonce again.
+fn access_key_query(signer: &Signer) -> serde_json::Value {
+    serde_json::json!({
+        "request_type": "view_access_key",
+        "finality": "optimistic",
+        "account_id": signer.account_id,
+        "public_key": signer.public_key.to_string(),
+    })
+}
+
+/// Nonce and block hash to use for the next transaction out of a `view_access_key` result.
+fn next_nonce_and_block_hash(result: &serde_json::Value) -> anyhow::Result<(u64, String)> {
+    let nonce = result["nonce"]
+        .as_u64()
+        .ok_or_else(|| anyhow::anyhow!("unexpected view_access_key response: {result}"))?;
+    let block_hash = result["block_hash"]
+        .as_str()
+        .ok_or_else(|| anyhow::anyhow!("unexpected view_access_key response: {result}"))?;
+    Ok((nonce + 1, block_hash.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signature as Ed25519Signature, Verifier, VerifyingKey};
+
+    use super::*;
+
+    // Reference vectors of near-api-js' transaction serialization tests, which match nearcore.
+    const BLOCK_HASH: &str = "244ZQ9cgj3CQ6bWBdytfrJMuMQ1jdXLFGnr4HhvtCTnM";
+    const SECRET_KEY: &str = "ed25519:2wyRcSwSuHtRVmkMCGjPwnzZmQLeXLzLLyED1NDMt4BjnKgQL6tF85yBx6Jr26D2dUNeC716RBoTxntVHsegogYw";
+    const PUBLIC_KEY_HEX: &str = "0f56a5f028dfc089ec7c39c1183b321b4d8f89ba5bec9e1762803cc2491f6ef8";
+    const BLOCK_HASH_HEX: &str = "0fa473fd26901df296be6adc4cc4df34d040efa2435224b6986910e630c2fef6";
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    fn action_hex(action: &Action) -> String {
+        hex(&borsh::to_vec(action).unwrap())
+    }
+
+    fn public_key() -> PublicKey {
+        Signer::from_secret_key("test.near", SECRET_KEY)
+            .unwrap()
+            .public_key()
+    }
+
+    #[test]
+    fn actions_are_serialized_like_nearcore() {
+        assert_eq!(action_hex(&Action::CreateAccount), "00");
+        assert_eq!(
+            action_hex(&Action::DeployContract {
+                code: vec![1, 2, 3]
+            }),
+            "0103000000010203"
+        );
+        assert_eq!(
+            action_hex(&Action::FunctionCall {
+                method_name: "qqq".to_string(),
+                args: vec![1, 2, 3],
+                gas: 1_000,
+                deposit: 1_000_000,
+            }),
+            "020300000071717103000000010203e80300000000000040420f00000000000000000000000000"
+        );
+        assert_eq!(
+            action_hex(&Action::Transfer { deposit: 123 }),
+            "037b000000000000000000000000000000"
+        );
+        assert_eq!(
+            action_hex(&Action::AddKey {
+                public_key: public_key(),
+                access_key: AccessKey {
+                    nonce: 0,
+                    permission: AccessKeyPermission::FunctionCall {
+                        allowance: None,
+                        receiver_id: "zzz".to_string(),
+                        method_names: vec!["www".to_string()],
+                    },
+                },
+            }),
+            format!("0500{PUBLIC_KEY_HEX}00000000000000000000030000007a7a7a0100000003000000777777")
+        );
+        assert_eq!(
+            action_hex(&Action::AddKey {
+                public_key: public_key(),
+                access_key: AccessKey {
+                    nonce: 7,
+                    permission: AccessKeyPermission::FullAccess,
+                },
+            }),
+            format!("0500{PUBLIC_KEY_HEX}070000000000000001")
+        );
+    }
+
+    #[test]
+    fn transfer_transaction_is_serialized_like_nearcore() {
+        let transaction = Transaction {
+            signer_id: "test.near".to_string(),
+            public_key: "ed25519:Anu7LYDfpLtkP7E16LT9imXF694BdQaa9ufVkQiwTQxC"
+                .parse()
+                .unwrap(),
+            nonce: 1,
+            receiver_id: "whatever.near".to_string(),
+            block_hash: bs58::decode(BLOCK_HASH)
+                .into_vec()
+                .unwrap()
+                .try_into()
+                .unwrap(),
+            actions: vec![Action::Transfer { deposit: 1 }],
+        };
+
+        assert_eq!(
+            hex(&borsh::to_vec(&transaction).unwrap()),
+            "09000000746573742e6e65617200917b3d268d4b58f7fec1b150bd68d69be3ee5d4cc39855e341538465\
+             bb77860d01000000000000000d00000077686174657665722e6e6561720fa473fd26901df296be6adc4c\
+             c4df34d040efa2435224b6986910e630c2fef6010000000301000000000000000000000000000000"
+        );
+    }
+
+    #[test]
+    fn signed_transaction_carries_a_valid_signature_over_its_hash() {
+        let signer = Signer::from_secret_key("test.near", SECRET_KEY).unwrap();
+        let transaction = signer
+            .sign(
+                1,
+                "123",
+                BLOCK_HASH,
+                vec![Action::CreateAccount, Action::Transfer { deposit: 123 }],
+            )
+            .unwrap();
+
+        let bytes = transaction.to_bytes();
+        let (unsigned, signature) = bytes.split_at(bytes.len() - 65);
+        assert_eq!(
+            hex(unsigned),
+            format!(
+                "09000000746573742e6e65617200{PUBLIC_KEY_HEX}0100000000000000\
+                 03000000313233{BLOCK_HASH_HEX}0200000000037b000000000000000000000000000000"
+            )
+        );
+
+        let hash: [u8; 32] = Sha256::digest(unsigned).into();
+        assert_eq!(transaction.hash(), bs58::encode(hash).into_string());
+
+        assert_eq!(signature[0], 0, "ed25519 signature tag");
+        let PublicKey::Ed25519(public_key) = signer.public_key();
+        let signature = Ed25519Signature::from_slice(&signature[1..]).unwrap();
+        VerifyingKey::from_bytes(&public_key)
+            .unwrap()
+            .verify(&hash, &signature)
+            .unwrap();
+    }
+}
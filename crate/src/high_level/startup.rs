@@ -0,0 +1,131 @@
+//! Setting up and starting the sandbox node.
+//!
+//! Shared by the async [`Sandbox`](super::Sandbox) and the blocking
+//! [`sync::Sandbox`](crate::sync::Sandbox), which only differ in how they spawn the node and
+//! wait for it. Everything else lives here, so both configure, retry and report the same way.
+
+use std::net::SocketAddr;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Instant;
+
+use super::{acquire_port, config, PortLock, SandboxConfig, DEFAULT_BIND_ADDR};
+use crate::launcher::{self, SandboxLauncher};
+
+/// How many times the sandbox gets started with fresh ports if it exits during startup, e.g.
+/// because some other process grabbed one of its ports in the meantime.
+const MAX_START_ATTEMPTS: usize = 3;
+
+/// Apply `config` to the config.json and genesis.json `init` created in `home_dir`.
+pub(crate) fn configure_home(home_dir: &Path, config: &SandboxConfig) -> anyhow::Result<()> {
+    config::set_sandbox_configs_with_config(home_dir, config)?;
+    config::set_sandbox_genesis_with_config(home_dir, config)
+}
+
+/// The ports and command of a single attempt at running the node.
+pub(crate) struct RunAttempt {
+    pub(crate) rpc_port_lock: PortLock,
+    pub(crate) net_port_lock: PortLock,
+    /// Address the RPC server binds to.
+    pub(crate) rpc_socket: SocketAddr,
+    pub(crate) command: Command,
+}
+
+impl RunAttempt {
+    /// Reserve the ports and build the command running the node in `home_dir` on them. The
+    /// ports are only held by their locks afterwards, so the node is free to bind them.
+    pub(crate) fn prepare(
+        launcher: &dyn SandboxLauncher,
+        version: &str,
+        home_dir: &Path,
+        config: &SandboxConfig,
+        log_vars: &[(String, String)],
+    ) -> anyhow::Result<Self> {
+        let rpc_ip = config.rpc_bind_addr.unwrap_or(DEFAULT_BIND_ADDR);
+        let net_ip = config.net_bind_addr.unwrap_or(DEFAULT_BIND_ADDR);
+        let mut rpc_port_lock = acquire_port(rpc_ip, config.rpc_port, "RPC")?;
+        let mut net_port_lock = acquire_port(net_ip, config.net_port, "network")?;
+
+        let rpc_socket = SocketAddr::new(rpc_ip, rpc_port_lock.port());
+        let rpc_addr = rpc_socket.to_string();
+        let net_addr = SocketAddr::new(net_ip, net_port_lock.port()).to_string();
+
+        let options = &[
+            "--home",
+            launcher::home_dir_arg(home_dir),
+            "run",
+            "--rpc-addr",
+            &rpc_addr,
+            "--network-addr",
+            &net_addr,
+        ];
+        let mut command = launcher.command(version, options, log_vars)?;
+        if config.forward_logs {
+            command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        }
+
+        rpc_port_lock.release_listener();
+        net_port_lock.release_listener();
+        Ok(Self {
+            rpc_port_lock,
+            net_port_lock,
+            rpc_socket,
+            command,
+        })
+    }
+}
+
+/// Whether to start over with new ports after attempt number `attempt` failed with `err`.
+pub(crate) fn should_retry(
+    config: &SandboxConfig,
+    attempt: usize,
+    exited: bool,
+    err: &anyhow::Error,
+) -> bool {
+    // Exiting right away is most likely a port which got taken between reserving and binding
+    // it, which fresh ports fix unless the user asked for fixed ones.
+    let fixed_ports = config.rpc_port.is_some() && config.net_port.is_some();
+    let retry = exited && !fixed_ports && attempt < MAX_START_ATTEMPTS;
+    if retry {
+        tracing::warn!(target: "sandbox", "{err:#}, retrying with new ports");
+    }
+    retry
+}
+
+/// How long to wait for the sandbox RPC to come up, `NEAR_RPC_TIMEOUT_SECS` or 10 seconds.
+pub(crate) fn rpc_timeout_secs() -> u64 {
+    match std::env::var("NEAR_RPC_TIMEOUT_SECS") {
+        Ok(secs) => secs
+            .parse::<u64>()
+            .expect("Failed to parse NEAR_RPC_TIMEOUT_SECS"),
+        Err(_) => 10,
+    }
+}
+
+/// Latest block height out of the body of the node's `/status` response.
+pub(crate) fn latest_block_height(status: &str) -> anyhow::Result<u64> {
+    let status: serde_json::Value = serde_json::from_str(status)?;
+    status["sync_info"]["latest_block_height"]
+        .as_u64()
+        .ok_or_else(|| anyhow::anyhow!("unexpected status response: {status}"))
+}
+
+/// Blocks produced per second between two `(height, time)` observations.
+pub(crate) fn block_rate(start: (u64, Instant), end: (u64, Instant)) -> f64 {
+    end.0.saturating_sub(start.0) as f64 / end.1.duration_since(start.1).as_secs_f64()
+}
+
+/// The outcome of measuring the block rate during startup. Failing to measure it doesn't fail
+/// the startup, the rate is just left unknown.
+pub(crate) fn measured_block_rate(rate: anyhow::Result<f64>) -> Option<f64> {
+    match rate {
+        Ok(rate) => {
+            tracing::info!(target: "sandbox", "Sandbox produces {rate:.1} blocks/s");
+            Some(rate)
+        }
+        Err(err) => {
+            tracing::warn!(target: "sandbox", "failed to measure the block rate: {err:#}");
+            None
+        }
+    }
+}
@@ -0,0 +1,462 @@
+//! Streaming the blocks of a running sandbox.
+//!
+//! A background task polls the RPC for new blocks, fetches their new chunks and, if asked
+//! for, the execution outcomes of the chunks' transactions, and hands them out through a
+//! bounded channel. Once the channel is full the task waits for the consumer, so a slow test
+//! holds up polling instead of piling up blocks in memory.
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_core::Stream;
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use super::rpc::{self, RpcError};
+
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(200);
+const DEFAULT_BUFFER: usize = 16;
+
+/// Which blocks a [`BlockStream`] yields.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Finality {
+    /// Every block as soon as the node has it.
+    #[default]
+    Optimistic,
+    /// Only blocks which are final, a couple of blocks behind the head.
+    Final,
+}
+
+impl Finality {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Optimistic => "optimistic",
+            Self::Final => "final",
+        }
+    }
+
+    /// Execution status the outcomes of transactions are waited for.
+    fn wait_until(self) -> &'static str {
+        match self {
+            Self::Optimistic => "EXECUTED_OPTIMISTIC",
+            Self::Final => "FINAL",
+        }
+    }
+}
+
+/// Options of a [`BlockStream`].
+#[derive(Debug, Clone)]
+pub struct BlockStreamOptions {
+    /// Height of the first block, the current head if not set. Heights which were skipped
+    /// by the chain, or garbage collected by a non-archival node, get skipped.
+    pub from_height: Option<u64>,
+    pub finality: Finality,
+    /// Also fetch the execution outcomes of the transactions, including the outcomes of the
+    /// receipts they produced. This waits for the transactions to be executed, which happens
+    /// in the blocks after the one including them.
+    pub include_outcomes: bool,
+    /// How long to wait before checking for new blocks once caught up with the head.
+    pub poll_interval: Duration,
+    /// Number of blocks fetched ahead of the consumer before polling pauses.
+    pub buffer: usize,
+}
+
+impl Default for BlockStreamOptions {
+    fn default() -> Self {
+        Self {
+            from_height: None,
+            finality: Finality::default(),
+            include_outcomes: true,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            buffer: DEFAULT_BUFFER,
+        }
+    }
+}
+
+/// A chunk newly included in a [`StreamedBlock`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamedChunk {
+    pub shard_id: u64,
+    /// The chunk as returned by the `chunk` RPC method.
+    pub chunk: Value,
+    /// Results of the `tx` RPC method for every transaction of the chunk, with the outcomes of
+    /// the transaction and its receipts. Empty unless `include_outcomes` is set.
+    pub outcomes: Vec<Value>,
+}
+
+impl StreamedChunk {
+    pub fn transactions(&self) -> &[Value] {
+        self.chunk["transactions"]
+            .as_array()
+            .map_or(&[], Vec::as_slice)
+    }
+
+    pub fn receipts(&self) -> &[Value] {
+        self.chunk["receipts"].as_array().map_or(&[], Vec::as_slice)
+    }
+}
+
+/// A block yielded by a [`BlockStream`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamedBlock {
+    pub height: u64,
+    pub hash: String,
+    /// The block as returned by the `block` RPC method.
+    pub block: Value,
+    /// The chunks included in this block. Shards which didn't produce a new chunk for this
+    /// block are left out.
+    pub chunks: Vec<StreamedChunk>,
+}
+
+/// Stream of the blocks of a sandbox, see [`Sandbox::block_stream`](super::Sandbox::block_stream).
+///
+/// Yields blocks in order of their height. An error ends the stream. Dropping the stream
+/// stops polling.
+pub struct BlockStream {
+    receiver: mpsc::Receiver<anyhow::Result<StreamedBlock>>,
+    task: JoinHandle<()>,
+}
+
+impl BlockStream {
+    /// Start polling the RPC server at `rpc_addr`. Must be called within a tokio runtime.
+    pub fn new(rpc_addr: impl Into<String>, options: BlockStreamOptions) -> Self {
+        let (sender, receiver) = mpsc::channel(options.buffer.max(1));
+        let task = tokio::spawn(poll_blocks(rpc_addr.into(), options, sender));
+        Self { receiver, task }
+    }
+}
+
+impl Stream for BlockStream {
+    type Item = anyhow::Result<StreamedBlock>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl Drop for BlockStream {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn poll_blocks(
+    rpc_addr: String,
+    options: BlockStreamOptions,
+    sender: mpsc::Sender<anyhow::Result<StreamedBlock>>,
+) {
+    let mut next_height = options.from_height;
+    loop {
+        let head = match head_height(&rpc_addr, options.finality).await {
+            Ok(head) => head,
+            Err(err) => {
+                let _ = sender.send(Err(err)).await;
+                return;
+            }
+        };
+
+        let mut height = next_height.unwrap_or(head);
+        while height <= head {
+            match fetch_block(&rpc_addr, height, &options).await {
+                Ok(Some(block)) => {
+                    // The stream was dropped.
+                    if sender.send(Ok(block)).await.is_err() {
+                        return;
+                    }
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    let _ = sender.send(Err(err)).await;
+                    return;
+                }
+            }
+            height += 1;
+        }
+        next_height = Some(height);
+
+        tokio::time::sleep(options.poll_interval).await;
+    }
+}
+
+async fn head_height(rpc_addr: &str, finality: Finality) -> anyhow::Result<u64> {
+    let block = rpc::call(rpc_addr, "block", json!({ "finality": finality.as_str() })).await?;
+    block["header"]["height"]
+        .as_u64()
+        .ok_or_else(|| anyhow::anyhow!("unexpected block response: {block}"))
+}
+
+/// Fetch the block at `height` with its new chunks, or `None` if there's no such block.
+async fn fetch_block(
+    rpc_addr: &str,
+    height: u64,
+    options: &BlockStreamOptions,
+) -> anyhow::Result<Option<StreamedBlock>> {
+    let block = match rpc::call(rpc_addr, "block", json!({ "block_id": height })).await {
+        Ok(block) => block,
+        Err(err) => {
+            let unknown = err
+                .downcast_ref::<RpcError>()
+                .is_some_and(|err| err.cause() == Some("UNKNOWN_BLOCK"));
+            return if unknown { Ok(None) } else { Err(err) };
+        }
+    };
+    let hash = block["header"]["hash"]
+        .as_str()
+        .ok_or_else(|| anyhow::anyhow!("unexpected block response: {block}"))?
+        .to_string();
+
+    let mut chunks = Vec::new();
+    for header in block["chunks"].as_array().map_or(&[][..], Vec::as_slice) {
+        if header["height_included"].as_u64() != Some(height) {
+            continue;
+        }
+        let chunk = rpc::call(
+            rpc_addr,
+            "chunk",
+            json!({ "chunk_id": header["chunk_hash"] }),
+        )
+        .await?;
+
+        let mut outcomes = Vec::new();
+        if options.include_outcomes {
+            for transaction in chunk["transactions"]
+                .as_array()
+                .map_or(&[][..], Vec::as_slice)
+            {
+                let params = json!({
+                    "tx_hash": transaction["hash"],
+                    "sender_account_id": transaction["signer_id"],
+                    "wait_until": options.finality.wait_until(),
+                });
+                outcomes.push(rpc::call(rpc_addr, "tx", params).await?);
+            }
+        }
+
+        chunks.push(StreamedChunk {
+            shard_id: header["shard_id"].as_u64().unwrap_or_default(),
+            chunk,
+            outcomes,
+        });
+    }
+
+    Ok(Some(StreamedBlock {
+        height,
+        hash,
+        block,
+        chunks,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::high_level::MockSandbox;
+
+    /// A chain whose head is at `head`, missing the blocks at `skipped`. Every block has a new
+    /// chunk for shard 0 with a single transaction, while shard 1 repeats an older chunk.
+    fn mock_chain(mock: &MockSandbox, head: Arc<AtomicU64>, skipped: &'static [u64]) {
+        mock.register("block", move |params| {
+            let height = match params["block_id"].as_u64() {
+                Some(height) if skipped.contains(&height) => {
+                    return Err(json!({
+                        "name": "HANDLER_ERROR",
+                        "cause": { "name": "UNKNOWN_BLOCK", "info": {} },
+                    }))
+                }
+                Some(height) => height,
+                None => head.load(Ordering::SeqCst),
+            };
+            Ok(json!({
+                "header": { "height": height, "hash": format!("block-{}", height) },
+                "chunks": [
+                    { "shard_id": 0, "chunk_hash": format!("chunk-{}", height), "height_included": height },
+                    { "shard_id": 1, "chunk_hash": "chunk-old", "height_included": 0 },
+                ],
+            }))
+        });
+        mock.register("chunk", |params| {
+            Ok(json!({
+                "header": { "chunk_hash": params["chunk_id"] },
+                "transactions": [{ "hash": format!("tx-{}", params["chunk_id"].as_str().unwrap()), "signer_id": "alice.near" }],
+                "receipts": [],
+            }))
+        });
+        mock.register("tx", |params| Ok(json!({ "request": params })));
+    }
+
+    async fn next(stream: &mut BlockStream) -> Option<anyhow::Result<StreamedBlock>> {
+        std::future::poll_fn(|cx| Pin::new(&mut *stream).poll_next(cx)).await
+    }
+
+    async fn wait_for_chunk_requests(mock: &MockSandbox, count: usize) {
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+        while mock.requests_for("chunk").len() < count {
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "no chunk request {}",
+                count
+            );
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    fn requested_heights(mock: &MockSandbox) -> Vec<u64> {
+        mock.requests_for("block")
+            .into_iter()
+            .filter_map(|request| request.params["block_id"].as_u64())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn skipped_heights_and_old_chunks_are_left_out() {
+        let mock = MockSandbox::start().unwrap();
+        mock_chain(&mock, Arc::new(AtomicU64::new(3)), &[2]);
+
+        let options = BlockStreamOptions {
+            from_height: Some(1),
+            ..BlockStreamOptions::default()
+        };
+        let mut stream = BlockStream::new(mock.rpc_addr.clone(), options);
+
+        let block = next(&mut stream).await.unwrap().unwrap();
+        assert_eq!(block.height, 1);
+        assert_eq!(block.hash, "block-1");
+        assert_eq!(block.chunks.len(), 1);
+        let chunk = &block.chunks[0];
+        assert_eq!(chunk.shard_id, 0);
+        assert_eq!(chunk.transactions()[0]["hash"], "tx-chunk-1");
+        assert!(chunk.receipts().is_empty());
+        assert_eq!(
+            chunk.outcomes,
+            [json!({ "request": {
+                "tx_hash": "tx-chunk-1",
+                "sender_account_id": "alice.near",
+                "wait_until": "EXECUTED_OPTIMISTIC",
+            } })]
+        );
+
+        assert_eq!(next(&mut stream).await.unwrap().unwrap().height, 3);
+        assert_eq!(requested_heights(&mock)[..3], [1, 2, 3]);
+        let chunk_ids: Vec<Value> = mock
+            .requests_for("chunk")
+            .into_iter()
+            .map(|request| request.params["chunk_id"].clone())
+            .collect();
+        assert_eq!(chunk_ids, ["chunk-1", "chunk-3"]);
+    }
+
+    #[tokio::test]
+    async fn final_stream_starts_at_the_final_head() {
+        let mock = MockSandbox::start().unwrap();
+        mock_chain(&mock, Arc::new(AtomicU64::new(5)), &[]);
+
+        let options = BlockStreamOptions {
+            finality: Finality::Final,
+            ..BlockStreamOptions::default()
+        };
+        let mut stream = BlockStream::new(mock.rpc_addr.clone(), options);
+
+        let block = next(&mut stream).await.unwrap().unwrap();
+        assert_eq!(block.height, 5);
+        assert_eq!(
+            block.chunks[0].outcomes[0]["request"]["wait_until"],
+            "FINAL"
+        );
+        assert_eq!(
+            mock.requests_for("block")[0].params,
+            json!({ "finality": "final" })
+        );
+    }
+
+    #[tokio::test]
+    async fn outcomes_are_only_fetched_if_asked_for() {
+        let mock = MockSandbox::start().unwrap();
+        mock_chain(&mock, Arc::new(AtomicU64::new(1)), &[]);
+
+        let options = BlockStreamOptions {
+            include_outcomes: false,
+            ..BlockStreamOptions::default()
+        };
+        let mut stream = BlockStream::new(mock.rpc_addr.clone(), options);
+
+        let block = next(&mut stream).await.unwrap().unwrap();
+        assert_eq!(block.chunks[0].transactions().len(), 1);
+        assert!(block.chunks[0].outcomes.is_empty());
+        assert!(mock.requests_for("tx").is_empty());
+    }
+
+    #[tokio::test]
+    async fn rpc_errors_end_the_stream() {
+        let mock = MockSandbox::start().unwrap();
+        mock_chain(&mock, Arc::new(AtomicU64::new(3)), &[]);
+        mock.register("chunk", |params| match params["chunk_id"].as_str() {
+            Some("chunk-2") => Err(json!({
+                "name": "HANDLER_ERROR",
+                "cause": { "name": "UNKNOWN_CHUNK", "info": {} },
+            })),
+            _ => Ok(json!({ "transactions": [], "receipts": [] })),
+        });
+
+        let options = BlockStreamOptions {
+            from_height: Some(1),
+            ..BlockStreamOptions::default()
+        };
+        let mut stream = BlockStream::new(mock.rpc_addr.clone(), options);
+
+        assert_eq!(next(&mut stream).await.unwrap().unwrap().height, 1);
+        let err = next(&mut stream).await.unwrap().unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<RpcError>().unwrap().cause(),
+            Some("UNKNOWN_CHUNK")
+        );
+        assert!(next(&mut stream).await.is_none());
+        assert_eq!(requested_heights(&mock), [1, 2]);
+    }
+
+    #[tokio::test]
+    async fn new_blocks_are_polled_for() {
+        let mock = MockSandbox::start().unwrap();
+        let head = Arc::new(AtomicU64::new(1));
+        mock_chain(&mock, head.clone(), &[]);
+
+        let options = BlockStreamOptions {
+            poll_interval: Duration::from_millis(10),
+            ..BlockStreamOptions::default()
+        };
+        let mut stream = BlockStream::new(mock.rpc_addr.clone(), options);
+
+        assert_eq!(next(&mut stream).await.unwrap().unwrap().height, 1);
+        head.store(3, Ordering::SeqCst);
+        assert_eq!(next(&mut stream).await.unwrap().unwrap().height, 2);
+        assert_eq!(next(&mut stream).await.unwrap().unwrap().height, 3);
+    }
+
+    #[tokio::test]
+    async fn a_full_buffer_pauses_polling() {
+        let mock = MockSandbox::start().unwrap();
+        mock_chain(&mock, Arc::new(AtomicU64::new(100)), &[]);
+
+        let options = BlockStreamOptions {
+            from_height: Some(1),
+            include_outcomes: false,
+            buffer: 1,
+            ..BlockStreamOptions::default()
+        };
+        let mut stream = BlockStream::new(mock.rpc_addr.clone(), options);
+
+        // One block waits in the channel while the next one waits to be sent.
+        wait_for_chunk_requests(&mock, 2).await;
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(requested_heights(&mock), [1, 2]);
+
+        assert_eq!(next(&mut stream).await.unwrap().unwrap().height, 1);
+        wait_for_chunk_requests(&mock, 3).await;
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(requested_heights(&mock), [1, 2, 3]);
+    }
+}
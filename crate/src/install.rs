@@ -0,0 +1,200 @@
+//! Coordination of sandbox binary installs between processes.
+//!
+//! Every install is staged in a temporary directory next to its destination and only moved
+//! into place once it has been fully unpacked and verified. Alongside the binary we keep a
+//! marker file with its expected size, which is written last, so a binary left behind by an
+//! interrupted install is detected and replaced instead of being executed.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use fs2::FileExt;
+
+const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(600);
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+fn marker_path(bin_path: &Path) -> PathBuf {
+    bin_path.with_extension("installed")
+}
+
+fn lock_path(bin_path: &Path) -> PathBuf {
+    bin_path.with_extension("lock")
+}
+
+/// Check whether `bin_path` holds a binary from an install that ran to completion.
+pub(crate) fn is_installed(bin_path: &Path) -> bool {
+    let Ok(metadata) = fs::metadata(bin_path) else {
+        return false;
+    };
+    let Ok(expected) = fs::read_to_string(marker_path(bin_path)) else {
+        return false;
+    };
+
+    expected.trim().parse::<u64>().ok() == Some(metadata.len())
+}
+
+/// Move a verified binary from the staging location into `bin_path` and mark the install
+/// as complete. Must be called while holding the [`InstallLock`] for `bin_path`.
+pub(crate) fn commit(staged: &Path, bin_path: &Path) -> anyhow::Result<()> {
+    let size = fs::metadata(staged)?.len();
+    if size == 0 {
+        anyhow::bail!("unpacked near-sandbox binary is empty");
+    }
+
+    // Drop the marker of a previous broken install before touching the binary, so a crash
+    // in between never leaves a stale marker pointing at a different file.
+    let marker = marker_path(bin_path);
+    if marker.exists() {
+        fs::remove_file(&marker)?;
+    }
+    fs::rename(staged, bin_path)?;
+
+    let staged_marker = staged.with_extension("installed");
+    fs::write(&staged_marker, size.to_string())?;
+    fs::rename(staged_marker, marker)?;
+
+    Ok(())
+}
+
+/// Exclusive lock over the install of a single sandbox binary, shared between processes.
+///
+/// The lock is released when dropped, or by the OS if the owning process dies. The lock file
+/// records the pid of its owner, so a lock that outlives its owner (e.g. on file systems
+/// without working advisory locks) is broken as soon as a waiter notices the owner is gone.
+/// Waiting for a live owner times out after 10 minutes by default, which can be changed through
+/// `NEAR_SANDBOX_INSTALL_LOCK_TIMEOUT_SECS`.
+pub(crate) struct InstallLock {
+    file: File,
+}
+
+impl InstallLock {
+    pub(crate) fn acquire(bin_path: &Path) -> anyhow::Result<Self> {
+        let timeout = match std::env::var("NEAR_SANDBOX_INSTALL_LOCK_TIMEOUT_SECS") {
+            Ok(secs) => Duration::from_secs(secs.parse()?),
+            Err(_) => DEFAULT_LOCK_TIMEOUT,
+        };
+        let path = lock_path(bin_path);
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)?;
+
+            if file.try_lock_exclusive().is_ok() {
+                // A stale lock file might have been removed between us opening and locking it,
+                // in which case a new one may be locked by someone else already.
+                if !same_file(&file, &path) {
+                    continue;
+                }
+                file.set_len(0)?;
+                write!(file, "{}", std::process::id())?;
+                file.flush()?;
+                return Ok(Self { file });
+            }
+
+            let owner = read_owner(&mut file);
+            if let Some(pid) = owner {
+                // Only remove the file we read the owner from, not a newer one which another
+                // waiter created after breaking the lock itself.
+                if !process_alive(pid) && same_file(&file, &path) {
+                    tracing::warn!(
+                        target: "sandbox",
+                        "Breaking stale install lock {} left by pid={}",
+                        path.display(),
+                        pid,
+                    );
+                    match fs::remove_file(&path) {
+                        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                            return Err(err.into())
+                        }
+                        _ => continue,
+                    }
+                }
+            }
+
+            if Instant::now() >= deadline {
+                anyhow::bail!(
+                    "timed out waiting for install lock {} held by pid={}",
+                    path.display(),
+                    owner.map_or_else(|| "unknown".to_string(), |pid| pid.to_string()),
+                );
+            }
+            std::thread::sleep(LOCK_POLL_INTERVAL);
+        }
+    }
+}
+
+impl Drop for InstallLock {
+    fn drop(&mut self) {
+        let _ = FileExt::unlock(&self.file);
+    }
+}
+
+fn read_owner(file: &mut File) -> Option<u32> {
+    let mut contents = String::new();
+    file.seek(SeekFrom::Start(0)).ok()?;
+    file.read_to_string(&mut contents).ok()?;
+    contents.trim().parse().ok()
+}
+
+/// Whether the opened `file` is still the one at `path`, and not removed or replaced.
+#[cfg(unix)]
+pub(crate) fn same_file(file: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(opened), Ok(current)) => opened.dev() == current.dev() && opened.ino() == current.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn same_file(_file: &File, path: &Path) -> bool {
+    path.exists()
+}
+
+#[cfg(target_os = "linux")]
+fn process_alive(pid: u32) -> bool {
+    Path::new(&format!("/proc/{pid}")).exists()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn process_alive(pid: u32) -> bool {
+    std::process::Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stderr(std::process::Stdio::null())
+        .status()
+        .map_or(true, |status| status.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_lock_of_dead_owner_is_broken_right_away() {
+        let dir = tempfile::tempdir().unwrap();
+        let bin_path = dir.path().join("near-sandbox");
+
+        // A lock which stays held although its owner is gone, as on file systems where
+        // advisory locks outlive processes. No process has the maximum pid.
+        let stale = File::create(lock_path(&bin_path)).unwrap();
+        stale.try_lock_exclusive().unwrap();
+        fs::write(lock_path(&bin_path), u32::MAX.to_string()).unwrap();
+
+        let start = Instant::now();
+        let lock = InstallLock::acquire(&bin_path).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(same_file(&lock.file, &lock_path(&bin_path)));
+        assert_eq!(
+            fs::read_to_string(lock_path(&bin_path)).unwrap(),
+            std::process::id().to_string()
+        );
+    }
+}
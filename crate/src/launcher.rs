@@ -0,0 +1,288 @@
+//! Pluggable backends for spawning the `near-sandbox` process.
+//!
+//! [`Sandbox`](crate::Sandbox) takes care of configs, genesis, ports and readiness; a
+//! [`SandboxLauncher`] only decides which command actually runs the node. This allows wrapping
+//! the binary (e.g. with `nice` or `taskset`), running it in a container or substituting a test
+//! double without reimplementing the rest of the sandbox setup.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::DownloadConfig;
+
+/// Builds the commands used to run `near-sandbox`.
+pub trait SandboxLauncher: Send + Sync {
+    /// Build the command that runs `near-sandbox` of the given `version` with `args`.
+    ///
+    /// The arguments contain the `--home` directory as well as the `init` or `run` subcommand
+    /// with their options. `envs` are the log environment variables of the node, like
+    /// `RUST_LOG`. Launchers running the node where the environment of the command doesn't
+    /// reach, e.g. in a container, have to forward them, like `docker run -e RUST_LOG=...`.
+    ///
+    /// The async [`Sandbox`](crate::Sandbox) calls this on its runtime, so it shouldn't block,
+    /// e.g. on downloads. Return the binary to install from [`download_config`] instead.
+    ///
+    /// [`download_config`]: SandboxLauncher::download_config
+    fn command(
+        &self,
+        version: &str,
+        args: &[&str],
+        envs: &[(String, String)],
+    ) -> anyhow::Result<Command>;
+
+    /// How the sandbox binary the commands run gets installed, if they run one at all. The
+    /// async [`Sandbox`](crate::Sandbox) installs it on a blocking thread before building any
+    /// command.
+    fn download_config(&self) -> anyhow::Result<Option<DownloadConfig>> {
+        Ok(None)
+    }
+}
+
+/// Runs the locally installed sandbox binary, installing it first if required.
+/// This is the launcher used by default.
+#[derive(Debug, Clone, Default)]
+pub struct LocalLauncher {
+    /// Where the binary is cached and how it gets downloaded. Taken from the environment
+    /// through [`DownloadConfig::from_env`] if not set.
+    pub download: Option<DownloadConfig>,
+}
+
+impl LocalLauncher {
+    /// Install and look up the binary according to `download` instead of the environment.
+    pub fn with_download_config(download: DownloadConfig) -> Self {
+        Self {
+            download: Some(download),
+        }
+    }
+}
+
+impl SandboxLauncher for LocalLauncher {
+    fn command(
+        &self,
+        version: &str,
+        args: &[&str],
+        envs: &[(String, String)],
+    ) -> anyhow::Result<Command> {
+        let bin_path = ensure_sandbox_bin(self.download.as_ref(), version)?;
+        let mut command = Command::new(bin_path);
+        command.args(args).envs(envs.iter().cloned());
+        Ok(command)
+    }
+
+    fn download_config(&self) -> anyhow::Result<Option<DownloadConfig>> {
+        download_config(self.download.as_ref()).map(Some)
+    }
+}
+
+/// Runs the locally installed sandbox binary through a wrapper program, e.g.
+/// `WrappedLauncher::new("taskset", ["-c", "0"])` runs `taskset -c 0 near-sandbox ...`.
+#[derive(Debug, Clone)]
+pub struct WrappedLauncher {
+    pub program: OsString,
+    pub args: Vec<OsString>,
+    /// Where the binary is cached and how it gets downloaded, see [`LocalLauncher::download`].
+    pub download: Option<DownloadConfig>,
+}
+
+impl WrappedLauncher {
+    pub fn new(
+        program: impl Into<OsString>,
+        args: impl IntoIterator<Item = impl Into<OsString>>,
+    ) -> Self {
+        Self {
+            program: program.into(),
+            args: args.into_iter().map(Into::into).collect(),
+            download: None,
+        }
+    }
+
+    /// Install and look up the binary according to `download` instead of the environment.
+    pub fn with_download_config(mut self, download: DownloadConfig) -> Self {
+        self.download = Some(download);
+        self
+    }
+}
+
+impl SandboxLauncher for WrappedLauncher {
+    fn command(
+        &self,
+        version: &str,
+        args: &[&str],
+        envs: &[(String, String)],
+    ) -> anyhow::Result<Command> {
+        let bin_path = ensure_sandbox_bin(self.download.as_ref(), version)?;
+        let mut command = Command::new(&self.program);
+        command
+            .args(&self.args)
+            .arg(bin_path)
+            .args(args)
+            .envs(envs.iter().cloned());
+        Ok(command)
+    }
+
+    fn download_config(&self) -> anyhow::Result<Option<DownloadConfig>> {
+        download_config(self.download.as_ref()).map(Some)
+    }
+}
+
+/// `download`, or the config taken from the environment if not set.
+fn download_config(download: Option<&DownloadConfig>) -> anyhow::Result<DownloadConfig> {
+    match download {
+        Some(download) => Ok(download.clone()),
+        None => DownloadConfig::from_env(),
+    }
+}
+
+/// Path of the sandbox binary of `version`, installed according to `download` or the
+/// environment.
+fn ensure_sandbox_bin(download: Option<&DownloadConfig>, version: &str) -> anyhow::Result<PathBuf> {
+    match download {
+        Some(download) => crate::ensure_sandbox_bin_with_config(version, download),
+        None => crate::ensure_sandbox_bin_with_version(version),
+    }
+}
+
+/// `home_dir` as argument of the `--home` option.
+pub(crate) fn home_dir_arg(home_dir: &Path) -> &str {
+    home_dir.to_str().expect("home_dir is valid utf8")
+}
+
+/// The command of `launcher` initializing a new node in `home_dir`.
+pub(crate) fn init_command(
+    launcher: &dyn SandboxLauncher,
+    version: &str,
+    home_dir: &Path,
+    log_vars: &[(String, String)],
+) -> anyhow::Result<Command> {
+    let options = &["--home", home_dir_arg(home_dir), "init", "--fast"];
+    launcher.command(version, options, log_vars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+
+    fn log_vars() -> Vec<(String, String)> {
+        vec![("RUST_LOG".to_string(), "debug".to_string())]
+    }
+
+    fn args(command: &Command) -> Vec<&OsStr> {
+        command.get_args().collect()
+    }
+
+    /// Runs the node in a container, which the log variables only reach as `-e` options.
+    struct ContainerLauncher;
+
+    impl SandboxLauncher for ContainerLauncher {
+        fn command(
+            &self,
+            version: &str,
+            args: &[&str],
+            envs: &[(String, String)],
+        ) -> anyhow::Result<Command> {
+            let mut command = Command::new("docker");
+            command.args(["run", "--rm"]);
+            for (key, value) in envs {
+                command.arg("-e").arg(format!("{}={}", key, value));
+            }
+            command.arg(format!("sandbox:{}", version)).args(args);
+            Ok(command)
+        }
+    }
+
+    #[test]
+    fn wrapped_launcher_runs_the_binary_through_the_program() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let download = DownloadConfig {
+            cache_dir: cache_dir.path().to_path_buf(),
+            ..DownloadConfig::default()
+        };
+        let bin_path = crate::bin_path_with_config("1.0.0", &download).unwrap();
+        let staged = bin_path.with_extension("staged");
+        std::fs::write(&staged, "#!/bin/sh\n").unwrap();
+        crate::install::commit(&staged, &bin_path).unwrap();
+
+        let launcher = WrappedLauncher::new("taskset", ["-c", "0"]).with_download_config(download);
+        let command =
+            init_command(&launcher, "1.0.0", Path::new("/tmp/home"), &log_vars()).unwrap();
+
+        assert_eq!(command.get_program(), "taskset");
+        assert_eq!(
+            args(&command),
+            [
+                OsStr::new("-c"),
+                OsStr::new("0"),
+                bin_path.as_os_str(),
+                OsStr::new("--home"),
+                OsStr::new("/tmp/home"),
+                OsStr::new("init"),
+                OsStr::new("--fast"),
+            ]
+        );
+        let envs: Vec<_> = command.get_envs().collect();
+        assert_eq!(envs, [(OsStr::new("RUST_LOG"), Some(OsStr::new("debug")))]);
+    }
+
+    #[test]
+    fn custom_launchers_get_the_log_environment() {
+        let command = init_command(
+            &ContainerLauncher,
+            "2.6.3",
+            Path::new("/tmp/home"),
+            &log_vars(),
+        )
+        .unwrap();
+        assert_eq!(command.get_program(), "docker");
+        assert_eq!(
+            args(&command),
+            [
+                "run",
+                "--rm",
+                "-e",
+                "RUST_LOG=debug",
+                "sandbox:2.6.3",
+                "--home",
+                "/tmp/home",
+                "init",
+                "--fast"
+            ]
+        );
+    }
+
+    /// Stands in for the node, echoing its log level and arguments.
+    #[cfg(unix)]
+    struct EchoLauncher;
+
+    #[cfg(unix)]
+    impl SandboxLauncher for EchoLauncher {
+        fn command(
+            &self,
+            _version: &str,
+            args: &[&str],
+            envs: &[(String, String)],
+        ) -> anyhow::Result<Command> {
+            let mut command = Command::new("sh");
+            command
+                .args(["-c", "echo \"$RUST_LOG\" \"$@\"", "sh"])
+                .args(args)
+                .envs(envs.iter().cloned());
+            Ok(command)
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_doubles_replace_the_node() {
+        let output = init_command(&EchoLauncher, "2.6.3", Path::new("/tmp/home"), &log_vars())
+            .unwrap()
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "debug --home /tmp/home init --fast\n"
+        );
+    }
+}
@@ -1,16 +1,18 @@
-use anyhow::{anyhow, Context};
-use binary_install::Cache;
-use fs2::FileExt;
+use anyhow::Context;
 use tokio::process::{Child, Command};
 
-use std::fs::File;
 use std::path::{Path, PathBuf};
 
+mod download;
 pub mod high_level;
+mod install;
+pub mod launcher;
 pub mod sync;
 
+pub use download::{default_cache_dir, render_artifact_url, DownloadConfig, DEFAULT_MIRROR};
 // Re-export important types for better user experience
-pub use high_level::{GenesisAccount, Sandbox, SandboxConfig};
+pub use high_level::{GenesisAccount, MockSandbox, Sandbox, SandboxConfig, Signer};
+pub use launcher::{LocalLauncher, SandboxLauncher, WrappedLauncher};
 
 // The current version of the sandbox node we want to point to.
 // Should be updated to the latest release of nearcore.
@@ -39,38 +41,27 @@ fn local_addr(port: u16) -> String {
     format!("0.0.0.0:{}", port)
 }
 
-// if the `SANDBOX_ARTIFACT_URL` env var is set, we short-circuit and use that.
-fn bin_url(version: &str) -> Option<String> {
-    if let Ok(val) = std::env::var("SANDBOX_ARTIFACT_URL") {
-        return Some(val);
-    }
-
-    Some(format!(
-        "https://s3-us-west-1.amazonaws.com/build.nearprotocol.com/nearcore/{}/{}/near-sandbox.tar.gz",
-        platform()?,
-        version,
-    ))
-}
-
-// Returns a path to the binary in the form of: `{home}/.near/near-sandbox-{version}` || `{$OUT_DIR}/.near/near-sandbox-{version}`
-fn download_path(version: &str) -> PathBuf {
-    let mut out = if cfg!(feature = "global_install") {
-        home::home_dir().expect("could not retrieve home_dir")
-    } else {
-        PathBuf::from(env!("OUT_DIR"))
-    };
-
-    out.push(".near");
-    out.push(format!("near-sandbox-{}", normalize_name(version)));
+// Returns a path to the directory of the version in the form of `{cache_dir}/near-sandbox-{version}`
+fn download_path(version: &str, config: &DownloadConfig) -> anyhow::Result<PathBuf> {
+    let out = config
+        .cache_dir
+        .join(format!("near-sandbox-{}", normalize_name(version)));
     if !out.exists() {
-        std::fs::create_dir_all(&out).expect("could not create download path");
+        std::fs::create_dir_all(&out)
+            .with_context(|| format!("could not create download path {}", out.display()))?;
     }
 
-    out
+    Ok(out)
 }
 
-/// Returns a path to the binary in the form of {home}/.near/near-sandbox-{version}/near-sandbox
+/// Returns a path to the binary in the form of {cache_dir}/near-sandbox-{version}/near-sandbox
 pub fn bin_path(version: &str) -> anyhow::Result<PathBuf> {
+    bin_path_with_config(version, &DownloadConfig::from_env()?)
+}
+
+/// Returns a path to the binary in the form of {cache_dir}/near-sandbox-{version}/near-sandbox,
+/// with `cache_dir` taken from the provided configuration.
+pub fn bin_path_with_config(version: &str, config: &DownloadConfig) -> anyhow::Result<PathBuf> {
     if let Ok(path) = std::env::var("NEAR_SANDBOX_BIN_PATH") {
         let path = PathBuf::from(path);
         if !path.exists() {
@@ -79,7 +70,7 @@ pub fn bin_path(version: &str) -> anyhow::Result<PathBuf> {
         return Ok(path);
     }
 
-    let mut buf = download_path(version);
+    let mut buf = download_path(version, config)?;
     buf.push("near-sandbox");
 
     Ok(buf)
@@ -93,27 +84,41 @@ fn normalize_name(input: &str) -> String {
 /// number from the nearcore project. Note that commits pushed to master within the latest 12h
 /// will likely not have the binaries made available quite yet.
 pub fn install_with_version(version: &str) -> anyhow::Result<PathBuf> {
-    if let Some(bin_path) = check_for_version(version)? {
+    install_with_config(version, &DownloadConfig::from_env()?)
+}
+
+/// Install the sandbox node given the version, downloading it with the provided retry, timeout
+/// and mirror settings.
+///
+/// Concurrent installs of the same version, also across processes, are serialized and the
+/// binary only becomes visible once it has been completely unpacked. Leftovers of an
+/// interrupted install get replaced.
+pub fn install_with_config(version: &str, config: &DownloadConfig) -> anyhow::Result<PathBuf> {
+    if let Some(bin_path) = check_for_version(version, config)? {
         return Ok(bin_path);
     }
 
-    // Download binary into temp dir
-    let bin_name = format!("near-sandbox-{}", normalize_name(version));
-    let dl_cache = Cache::at(&download_path(version));
-    let bin_path = bin_url(version).ok_or_else(|| {
-        anyhow!("Unsupported platform: only linux-x86 and darwin-arm are supported")
-    })?;
-    let dl = dl_cache
-        .download(true, &bin_name, &["near-sandbox"], &bin_path)
-        .map_err(anyhow::Error::msg)
-        .with_context(|| "unable to download near-sandbox")?
-        .ok_or_else(|| anyhow!("Could not install near-sandbox"))?;
+    let dest = download_path(version, config)?.join("near-sandbox");
+    let _lock = install::InstallLock::acquire(&dest)?;
 
-    let path = dl.binary("near-sandbox").map_err(anyhow::Error::msg)?;
+    // Check again after acquiring if someone else finished the install meanwhile
+    if install::is_installed(&dest) {
+        return Ok(dest);
+    }
+
+    let urls = config.artifact_urls(version, platform())?;
+    let tarball = config
+        .fetch(&urls)
+        .with_context(|| "unable to download near-sandbox")?;
+
+    // Unpack binary into temp dir
+    let temp_dir = tempfile::tempdir_in(download_path(version, config)?)?;
+    let path = temp_dir.path().join("near-sandbox");
+    download::unpack_binary(&tarball, &path).with_context(|| "Could not install near-sandbox")?;
 
     // Move near-sandbox binary to correct location from temp folder.
-    let dest = download_path(version).join("near-sandbox");
-    std::fs::rename(path, &dest)?;
+    install::commit(&path, &dest)?;
+    println!("Installed near-sandbox into {}", dest.display());
 
     Ok(dest)
 }
@@ -124,38 +129,12 @@ pub fn install() -> anyhow::Result<PathBuf> {
     ensure_sandbox_bin_with_version(DEFAULT_NEAR_SANDBOX_VERSION)
 }
 
-fn installable(bin_path: &Path) -> anyhow::Result<Option<std::fs::File>> {
-    // Sandbox bin already exists
-    if bin_path.exists() {
-        return Ok(None);
-    }
-
-    let mut lockpath = bin_path.to_path_buf();
-    lockpath.set_extension("lock");
-
-    // Acquire the lockfile
-    let lockfile = File::create(lockpath)?;
-    lockfile.lock_exclusive()?;
-
-    // Check again after acquiring if no one has written to the dest path
-    if bin_path.exists() {
-        Ok(None)
-    } else {
-        Ok(Some(lockfile))
-    }
-}
-
 pub fn ensure_sandbox_bin() -> anyhow::Result<PathBuf> {
     ensure_sandbox_bin_with_version(DEFAULT_NEAR_SANDBOX_VERSION)
 }
 
 pub fn run_with_options(options: &[&str]) -> anyhow::Result<Child> {
-    let bin_path = crate::ensure_sandbox_bin()?;
-    Command::new(&bin_path)
-        .args(options)
-        .envs(crate::log_vars())
-        .spawn()
-        .with_context(|| format!("failed to run sandbox using '{}'", bin_path.display()))
+    run_with_options_with_version(options, DEFAULT_NEAR_SANDBOX_VERSION)
 }
 
 pub fn run(home_dir: impl AsRef<Path>, rpc_port: u16, network_port: u16) -> anyhow::Result<Child> {
@@ -173,24 +152,42 @@ pub fn init(home_dir: impl AsRef<Path>) -> anyhow::Result<Child> {
 }
 
 pub fn ensure_sandbox_bin_with_version(version: &str) -> anyhow::Result<PathBuf> {
-    let mut bin_path = bin_path(version)?;
-    if let Some(lockfile) = installable(&bin_path)? {
-        bin_path = install_with_version(version)?;
-        println!("Installed near-sandbox into {}", bin_path.to_str().unwrap());
-        std::env::set_var("NEAR_SANDBOX_BIN_PATH", bin_path.as_os_str());
-        fs2::FileExt::unlock(&lockfile)?;
+    ensure_sandbox_bin_with_config(version, &DownloadConfig::from_env()?)
+}
+
+/// Return the path to the sandbox binary of the given version, installing it into the cache
+/// configured in `config` if it's not there yet.
+pub fn ensure_sandbox_bin_with_config(
+    version: &str,
+    config: &DownloadConfig,
+) -> anyhow::Result<PathBuf> {
+    if let Some(bin_path) = check_for_version(version, config)? {
+        return Ok(bin_path);
     }
 
-    Ok(bin_path)
+    install_with_config(version, config)
 }
 
 pub fn run_with_options_with_version(options: &[&str], version: &str) -> anyhow::Result<Child> {
-    let bin_path = ensure_sandbox_bin_with_version(version)?;
-    Command::new(&bin_path)
-        .args(options)
-        .envs(crate::log_vars())
+    run_with_launcher(&LocalLauncher::default(), options, version)
+}
+
+/// Run the sandbox node with the provided options through a custom launcher.
+pub fn run_with_launcher(
+    launcher: &dyn SandboxLauncher,
+    options: &[&str],
+    version: &str,
+) -> anyhow::Result<Child> {
+    let command = launcher.command(version, options, &log_vars())?;
+    spawn(command, "run")
+}
+
+/// Spawn a command built by a launcher, `action` describing it in the error message.
+pub(crate) fn spawn(command: std::process::Command, action: &str) -> anyhow::Result<Child> {
+    let program = PathBuf::from(command.get_program());
+    Command::from(command)
         .spawn()
-        .with_context(|| format!("failed to run sandbox using '{}'", bin_path.display()))
+        .with_context(|| format!("failed to {action} sandbox using '{}'", program.display()))
 }
 
 pub fn run_with_version(
@@ -217,15 +214,21 @@ pub fn run_with_version(
 
 /// Initialize a sandbox node with the provided version and home directory.
 pub fn init_with_version(home_dir: impl AsRef<Path>, version: &str) -> anyhow::Result<Child> {
-    let bin_path = ensure_sandbox_bin_with_version(version)?;
-    let home_dir = home_dir.as_ref().to_str().unwrap();
-    Command::new(&bin_path)
-        .envs(log_vars())
-        .args(["--home", home_dir, "init", "--fast"])
-        .spawn()
-        .with_context(|| format!("failed to init sandbox using '{}'", bin_path.display()))
+    init_with_launcher(&LocalLauncher::default(), home_dir, version)
+}
+
+/// Initialize a sandbox node with the provided version and home directory through a custom
+/// launcher.
+pub fn init_with_launcher(
+    launcher: &dyn SandboxLauncher,
+    home_dir: impl AsRef<Path>,
+    version: &str,
+) -> anyhow::Result<Child> {
+    let command = launcher::init_command(launcher, version, home_dir.as_ref(), &log_vars())?;
+    spawn(command, "init")
 }
 
+/// Log variables for the node taken from `NEAR_SANDBOX_LOG` and `NEAR_SANDBOX_LOG_STYLE`.
 fn log_vars() -> Vec<(String, String)> {
     let mut vars = Vec::new();
     if let Ok(val) = std::env::var("NEAR_SANDBOX_LOG") {
@@ -240,15 +243,15 @@ fn log_vars() -> Vec<(String, String)> {
 /// Check if the sandbox version is already downloaded to the bin path.
 /// It does not disambiguate between a commit hash and a tagged version, so it's recommeded to
 /// pick one format and stick to it.
-fn check_for_version(version: &str) -> anyhow::Result<Option<PathBuf>> {
+fn check_for_version(version: &str, config: &DownloadConfig) -> anyhow::Result<Option<PathBuf>> {
     // short circuit if we are using the sandbox binary from the environment
-    if let Ok(bin_path) = &std::env::var("NEAR_SANDBOX_BIN_PATH") {
-        return Ok(Some(PathBuf::from(bin_path)));
+    if std::env::var("NEAR_SANDBOX_BIN_PATH").is_ok() {
+        return bin_path_with_config(version, config).map(Some);
     }
 
-    // version saved under {home}/.near/near-sandbox-{version}/near-sandbox
-    let out_dir = download_path(version).join("near-sandbox");
-    if !out_dir.exists() {
+    // version saved under {cache_dir}/near-sandbox-{version}/near-sandbox
+    let out_dir = download_path(version, config)?.join("near-sandbox");
+    if !install::is_installed(&out_dir) {
         return Ok(None);
     }
 
@@ -1,34 +1,297 @@
-use std::path::Path;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
 use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use tempfile::TempDir;
+use tracing::info;
+
+use crate::high_level::startup::{self, RunAttempt};
+use crate::high_level::{self, logs, Metrics, PortLock, ProcessStats};
+use crate::launcher::{self, LocalLauncher, SandboxLauncher};
+use crate::SandboxConfig;
 
 pub fn run_with_options(options: &[&str]) -> anyhow::Result<Child> {
-    let bin_path = crate::ensure_sandbox_bin()?;
-    Command::new(bin_path)
-        .args(options)
-        .envs(crate::log_vars())
-        .spawn()
-        .map_err(Into::into)
+    run_with_options_with_version(options, crate::DEFAULT_NEAR_SANDBOX_VERSION)
 }
 
 pub fn run(home_dir: impl AsRef<Path>, rpc_port: u16, network_port: u16) -> anyhow::Result<Child> {
-    let home_dir = home_dir.as_ref().to_str().unwrap();
-    run_with_options(&[
-        "--home",
+    run_with_version(
         home_dir,
-        "run",
-        "--rpc-addr",
-        &crate::local_addr(rpc_port),
-        "--network-addr",
-        &crate::local_addr(network_port),
-    ])
+        rpc_port,
+        network_port,
+        crate::DEFAULT_NEAR_SANDBOX_VERSION,
+    )
 }
 
 pub fn init(home_dir: impl AsRef<Path>) -> anyhow::Result<Child> {
-    let bin_path = crate::ensure_sandbox_bin()?;
-    let home_dir = home_dir.as_ref().to_str().unwrap();
-    Command::new(bin_path)
-        .envs(crate::log_vars())
-        .args(["--home", home_dir, "init"])
+    init_with_version(home_dir, crate::DEFAULT_NEAR_SANDBOX_VERSION)
+}
+
+pub fn run_with_options_with_version(options: &[&str], version: &str) -> anyhow::Result<Child> {
+    run_with_launcher(&LocalLauncher::default(), options, version)
+}
+
+/// Run the sandbox node with the provided options through a custom launcher.
+pub fn run_with_launcher(
+    launcher: &dyn SandboxLauncher,
+    options: &[&str],
+    version: &str,
+) -> anyhow::Result<Child> {
+    let command = launcher.command(version, options, &crate::log_vars())?;
+    spawn(command, "run")
+}
+
+/// Spawn a command built by a launcher, `action` describing it in the error message.
+fn spawn(mut command: Command, action: &str) -> anyhow::Result<Child> {
+    let program = PathBuf::from(command.get_program());
+    command
         .spawn()
-        .map_err(Into::into)
+        .with_context(|| format!("failed to {action} sandbox using '{}'", program.display()))
+}
+
+pub fn run_with_version(
+    home_dir: impl AsRef<Path>,
+    rpc_port: u16,
+    network_port: u16,
+    version: &str,
+) -> anyhow::Result<Child> {
+    let home_dir = home_dir.as_ref().to_str().unwrap();
+
+    run_with_options_with_version(
+        &[
+            "--home",
+            home_dir,
+            "run",
+            "--rpc-addr",
+            &crate::local_addr(rpc_port),
+            "--network-addr",
+            &crate::local_addr(network_port),
+        ],
+        version,
+    )
+}
+
+/// Initialize a sandbox node with the provided version and home directory.
+pub fn init_with_version(home_dir: impl AsRef<Path>, version: &str) -> anyhow::Result<Child> {
+    init_with_launcher(&LocalLauncher::default(), home_dir, version)
+}
+
+/// Initialize a sandbox node with the provided version and home directory through a custom
+/// launcher.
+pub fn init_with_launcher(
+    launcher: &dyn SandboxLauncher,
+    home_dir: impl AsRef<Path>,
+    version: &str,
+) -> anyhow::Result<Child> {
+    let command = launcher::init_command(launcher, version, home_dir.as_ref(), &crate::log_vars())?;
+    spawn(command, "init")
+}
+
+/// A blocking sandbox instance that can be used to launch local near network to test against.
+///
+/// This is the counterpart of [`crate::Sandbox`] for non-async test suites and build scripts;
+/// it sets up genesis, accounts and ports the same way and waits until the node is ready.
+pub struct Sandbox {
+    pub home_dir: TempDir,
+    pub rpc_addr: String,
+    pub rpc_port_lock: PortLock,
+    pub net_port_lock: PortLock,
+    process: Child,
+    blocks_per_second: Option<f64>,
+}
+
+impl Sandbox {
+    /// Start a new sandbox with the default near-sandbox-utils version.
+    pub fn start_sandbox() -> anyhow::Result<Self> {
+        Self::start_sandbox_with_config_and_version(
+            SandboxConfig::default(),
+            crate::DEFAULT_NEAR_SANDBOX_VERSION,
+        )
+    }
+
+    /// Start a new sandbox with the given near-sandbox-utils version.
+    ///
+    /// # Arguments
+    /// * `version` - the version of the near-sandbox-utils to use.
+    ///
+    pub fn start_sandbox_with_version(version: &str) -> anyhow::Result<Self> {
+        Self::start_sandbox_with_config_and_version(SandboxConfig::default(), version)
+    }
+
+    /// Start a new sandbox with the custom configuration and default version.
+    ///
+    /// # Arguments
+    /// * `config` - custom configuration for the sandbox
+    ///
+    pub fn start_sandbox_with_config(config: SandboxConfig) -> anyhow::Result<Self> {
+        Self::start_sandbox_with_config_and_version(config, crate::DEFAULT_NEAR_SANDBOX_VERSION)
+    }
+
+    /// Start a new sandbox with a custom configuration and specific near-sandbox-utils version.
+    ///
+    /// # Arguments
+    /// * `config` - custom configuration for the sandbox
+    /// * `version` - the version of the near-sandbox-utils to use
+    ///
+    pub fn start_sandbox_with_config_and_version(
+        config: SandboxConfig,
+        version: &str,
+    ) -> anyhow::Result<Self> {
+        Self::start_sandbox_with_launcher(config, version, &LocalLauncher::default())
+    }
+
+    /// Start a new sandbox with a custom configuration and specific near-sandbox-utils version,
+    /// spawning the node through a custom launcher.
+    ///
+    /// # Arguments
+    /// * `config` - custom configuration for the sandbox
+    /// * `version` - the version of the near-sandbox-utils to use
+    /// * `launcher` - the launcher used to run `near-sandbox`, see [`SandboxLauncher`]
+    ///
+    pub fn start_sandbox_with_launcher(
+        config: SandboxConfig,
+        version: &str,
+        launcher: &dyn SandboxLauncher,
+    ) -> anyhow::Result<Self> {
+        let log_vars = config.log_vars();
+        let home_dir = Self::init_home_dir_with_version(version, launcher, &log_vars)?;
+        startup::configure_home(home_dir.path(), &config)?;
+
+        let mut attempt = 1;
+        loop {
+            let run = RunAttempt::prepare(launcher, version, home_dir.path(), &config, &log_vars)?;
+            let mut child = spawn(run.command, "run")?;
+            if config.forward_logs {
+                forward_logs(&mut child, run.rpc_port_lock.port());
+            }
+
+            info!(target: "sandbox", "Started up sandbox at {} with pid={:?}", run.rpc_socket, child.id());
+
+            let rpc_addr = high_level::rpc_url(run.rpc_socket);
+
+            match Self::wait_until_ready(&rpc_addr, &mut child) {
+                Ok(()) => {
+                    let mut sandbox = Self {
+                        home_dir,
+                        rpc_addr,
+                        rpc_port_lock: run.rpc_port_lock,
+                        net_port_lock: run.net_port_lock,
+                        process: child,
+                        blocks_per_second: None,
+                    };
+                    if let Some(window) = config.measure_block_rate {
+                        let rate = sandbox.measure_blocks_per_second(window);
+                        sandbox.blocks_per_second = startup::measured_block_rate(rate);
+                    }
+                    return Ok(sandbox);
+                }
+                Err(err) => {
+                    let exited = matches!(child.try_wait(), Ok(Some(_)));
+                    if startup::should_retry(&config, attempt, exited, &err) {
+                        attempt += 1;
+                        continue;
+                    }
+
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    /// Blocks per second the node produced right after startup, if measured because of
+    /// [`SandboxConfig::measure_block_rate`].
+    pub fn blocks_per_second(&self) -> Option<f64> {
+        self.blocks_per_second
+    }
+
+    /// Measure the rate at which the node produces blocks over the given `window`.
+    pub fn measure_blocks_per_second(&self, window: Duration) -> anyhow::Result<f64> {
+        let start = (self.latest_block_height()?, Instant::now());
+        std::thread::sleep(window);
+        let end = (self.latest_block_height()?, Instant::now());
+        Ok(startup::block_rate(start, end))
+    }
+
+    fn latest_block_height(&self) -> anyhow::Result<u64> {
+        let status = ureq::get(&format!("{}/status", self.rpc_addr))
+            .call()?
+            .into_string()?;
+        startup::latest_block_height(&status)
+    }
+
+    /// Scrape the Prometheus metrics of the node.
+    pub fn metrics(&self) -> anyhow::Result<Metrics> {
+        let text = ureq::get(&format!("{}/metrics", self.rpc_addr))
+            .call()?
+            .into_string()?;
+        Metrics::parse(&text)
+    }
+
+    /// Resources currently used by the node process. Only available on Linux.
+    pub fn process_stats(&self) -> anyhow::Result<ProcessStats> {
+        ProcessStats::of_process(self.process.id())
+    }
+
+    fn init_home_dir_with_version(
+        version: &str,
+        launcher: &dyn SandboxLauncher,
+        log_vars: &[(String, String)],
+    ) -> anyhow::Result<TempDir> {
+        let home_dir = tempfile::tempdir()?;
+        let command = launcher::init_command(launcher, version, home_dir.path(), log_vars)?;
+        let output = spawn(command, "init")?.wait_with_output()?;
+        info!(target: "sandbox", "sandbox init: {:?}", output);
+
+        Ok(home_dir)
+    }
+
+    fn wait_until_ready(rpc: &str, child: &mut Child) -> anyhow::Result<()> {
+        let timeout_secs = startup::rpc_timeout_secs();
+        let agent = ureq::AgentBuilder::new()
+            .timeout(Duration::from_millis(500))
+            .build();
+
+        for _ in 0..timeout_secs * 2 {
+            std::thread::sleep(Duration::from_millis(500));
+            if let Some(status) = child.try_wait()? {
+                anyhow::bail!("Sandbox exited during startup with {status}");
+            }
+            // Any HTTP response, even an error status, means the RPC server is up.
+            let response = agent.get(&format!("{}/status", rpc)).call();
+            if !matches!(response, Err(ureq::Error::Transport(_))) {
+                return Ok(());
+            }
+        }
+        Err(anyhow::anyhow!(
+            "Sandbox didn't start with the provided timeout"
+        ))
+    }
+}
+
+/// Forward the output of the sandbox into `tracing` from background threads.
+fn forward_logs(child: &mut Child, port: u16) {
+    let pid = Some(child.id());
+    if let Some(stdout) = child.stdout.take() {
+        std::thread::spawn(move || logs::forward(BufReader::new(stdout), pid, port));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        std::thread::spawn(move || logs::forward(BufReader::new(stderr), pid, port));
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        info!(
+            target: "sandbox",
+            "Cleaning up sandbox: pid={:?}",
+            self.process.id()
+        );
+
+        self.process.kill().expect("failed to kill sandbox");
+        let _ = self.process.wait();
+    }
 }